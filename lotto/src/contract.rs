@@ -1,20 +1,48 @@
 use crate::msg::{
-    ConfigResponse, ExecuteMsg, InstantiateMsg, LottoResponse, LottosResponse,
-    ProtocolBalancesResponse, QueryMsg,
+    AddressLotto, AddressLottosResponse, BeneficiariesResponse, BeneficiaryResponse,
+    ClaimableResponse, ConfigResponse, CrossChainPayoutMsg, DenomSolvency, ExecuteMsg,
+    HooksResponse, InstantiateMsg, LottoHook, LottoResponse, LottosResponse,
+    NoisFeeReserveResponse, NonceResponse, OddsResponse, PayoutChannelResponse, PayoutResponse,
+    PlayerBalanceResponse, ProtocolBalancesResponse, QueryMsg, RequestPacket,
+    RevenueDestinationMsg, RevenueRecipientMsg, RevenueRecipientsResponse, SolvencyResponse,
+    SubscriptionCriteriaMsg, SubscriptionResponse, SubscriptionsResponse, SudoMsg,
+    SwapRouterExecuteMsg, SwapRouterMsg, TicketAuthorization, VerifyDrawResponse, VoteTally,
+    VotesResponse, WithdrawalResponse, WithdrawalsResponse, CONFIG_RESPONSE_VERSION,
 };
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    ensure_eq, to_binary, Attribute, BankMsg, Coin, Deps, DepsMut, Env, MessageInfo, Order,
-    QueryResponse, Response, StdResult, Uint128, WasmMsg,
+    ensure_eq, to_binary, Addr, Attribute, BankMsg, Binary, Coin, CosmosMsg, Decimal, Deps,
+    DepsMut, Empty, Env, Event, HexBinary, MessageInfo, Order, QueryResponse, Reply, Response,
+    StdError, StdResult, Storage, SubMsg, SubMsgResult, Timestamp, Uint128, Uint256, WasmMsg,
 };
 use cw_storage_plus::Bound;
-use nois::{NoisCallback, ProxyExecuteMsg};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 
 // use cw2::set_contract_version;
 
 use crate::error::ContractError;
-use crate::state::{Config, Lotto, CONFIG, LOTTOS, PROTOCOL_BALANCES};
+use crate::events::{self, coins_string, Commissions};
+use crate::hooks::hook_msgs;
+use crate::ibc::{ibc_hooks_sender, is_local_address, payout_msg, transfer_sequence};
+use crate::ledger::{
+    add_escrow, credit_claimable, credit_player_balance, debit_player_balance, release_escrow,
+};
+use crate::randomness::{provider, ProviderCallback, RandomnessProvider, RandomnessReady};
+use crate::reply::{CONVERT_REVENUE_REPLY_ID, HOOK_REPLY_ID, IBC_PAYOUT_REPLY_ID};
+use crate::signature::{pubkey_address, verify_authorization};
+use crate::state::{
+    Beneficiary, CommitReveal, CommunityPoolMode, Config, CrossChainParticipant, CrossChainPayout,
+    DrawProof, Lotto, LottoStatus, PayoutChannel, PendingConversion, RandomnessSource,
+    RevenueDestination, RevenueRecipient, Settlement, Stats, Subscription, SubscriptionCriteria,
+    Tally, TicketsRecord, VoteWeighting, WinningsRecord, Withdrawal, BENEFICIARIES, CLAIMABLE,
+    CONFIG, CREATOR_STATS, CREDIT_WINNINGS, CROSS_CHAIN_ACCOUNTS, CROSS_CHAIN_PARTICIPANTS, ESCROW,
+    HOOKS, IBC_PAYOUTS, IBC_PAYOUTS_SENT, LOTTOS, LOTTO_PAYOUT_CHANNELS, NOIS_FEE_RESERVE, NONCES,
+    PAYOUT_CHANNELS, PENDING_CONVERSION, PLAYER_BALANCES, PROTOCOL_BALANCES, REVENUE_RECIPIENTS,
+    STATS, SUBSCRIPTIONS, SUBSCRIPTION_CURSOR, TICKETS, UNTRACKED_PAYOUTS, VOTES, VOTE_TALLIES,
+    WINNINGS, WITHDRAWALS, WITHDRAWAL_COUNT,
+};
 
 /*
 // version info for migration info
@@ -22,6 +50,21 @@ const CONTRACT_NAME: &str = "crates.io:lotto";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 */
 
+// How long the creator of a commit-reveal lotto has to reveal its secret after expiration
+const REVEAL_PERIOD_SECONDS: u64 = 24 * 60 * 60;
+// How many winners are paid by a `Settle` call that doesn't set a limit
+const DEFAULT_SETTLE_LIMIT: u32 = 50;
+// Max number of entries a list query returns
+const MAX_LIMIT: u32 = 100;
+// Max number of lottos a lotto list query looks at, matching the filters or not
+const MAX_SCANNED_LOTTOS: usize = 500;
+// Longest lotto duration unless configured otherwise
+const DEFAULT_MAX_DURATION_SECONDS: u64 = 30 * 24 * 60 * 60;
+// Subscriptions checked when a lotto is created, so that creating a lotto costs bounded gas
+const MAX_SUBSCRIPTIONS_PER_LOTTO: usize = 20;
+// Most tickets a subscription buys in one lotto
+const MAX_TICKETS_PER_SUBSCRIPTION: u32 = 5;
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
     deps: DepsMut,
@@ -49,6 +92,14 @@ pub fn instantiate(
     if protocol_commission_percent + creator_commission_percent >= 100 {
         return Err(ContractError::IncorrectRates {});
     }
+    let community_pool_mode = msg
+        .community_pool_mode
+        .unwrap_or(CommunityPoolMode::Address);
+    validate_community_pool_mode(&community_pool_mode)?;
+    let swap_router = msg
+        .swap_router
+        .map(|router| deps.api.addr_validate(&router))
+        .transpose()?;
 
     let cnfg = Config {
         manager: addr,
@@ -58,6 +109,14 @@ pub fn instantiate(
         protocol_commission_percent,
         creator_commission_percent,
         is_paused: false,
+        randomness_source: msg.randomness_source.unwrap_or(RandomnessSource::Nois),
+        max_duration_seconds: msg
+            .max_duration_seconds
+            .unwrap_or(DEFAULT_MAX_DURATION_SECONDS),
+        allowed_denoms: msg.allowed_denoms.unwrap_or_default(),
+        community_pool_mode,
+        swap_router,
+        reserve_pays_nois_fee: false,
     };
 
     CONFIG.save(deps.storage, &cnfg)?;
@@ -80,6 +139,10 @@ pub fn execute(
             duration_seconds,
             number_of_winners,
             community_pool_percentage,
+            commitment,
+            beneficiary,
+            vote_weighting,
+            series,
         } => execute_create_lotto(
             deps,
             env,
@@ -88,9 +151,80 @@ pub fn execute(
             duration_seconds,
             number_of_winners,
             community_pool_percentage,
+            commitment,
+            beneficiary,
+            vote_weighting,
+            series,
+        ),
+        ExecuteMsg::BuyTicket {
+            lotto_id,
+            entropy,
+            vote,
+        } => {
+            if info.funds.is_empty() {
+                execute_buy_ticket_from_balance(deps, env, info.sender, lotto_id, entropy, vote)
+            } else {
+                execute_buy_ticket(deps, env, info.sender, info.funds, lotto_id, entropy, vote)
+            }
+        }
+        ExecuteMsg::BuyTicketWithSignature {
+            lotto_id,
+            buyer_pubkey,
+            signature,
+            nonce,
+            deadline,
+        } => execute_buy_ticket_with_signature(
+            deps,
+            env,
+            lotto_id,
+            buyer_pubkey,
+            signature,
+            nonce,
+            deadline,
+        ),
+        ExecuteMsg::DepositBalance { recipient } => execute_deposit_balance(deps, info, recipient),
+        ExecuteMsg::WithdrawBalance { coins } => execute_withdraw_balance(deps, info, coins),
+        ExecuteMsg::SetCreditWinnings { enabled } => {
+            execute_set_credit_winnings(deps, info, enabled)
+        }
+        ExecuteMsg::Subscribe {
+            criteria,
+            tickets_per_lotto,
+            max_spend,
+        } => execute_subscribe(deps, info, criteria, tickets_per_lotto, max_spend),
+        ExecuteMsg::Unsubscribe {} => execute_unsubscribe(deps, info),
+        ExecuteMsg::BuyTicketIbc {
+            lotto_id,
+            channel,
+            original_sender,
+            entropy,
+            vote,
+            payout,
+        } => execute_buy_ticket_ibc(
+            deps,
+            env,
+            info,
+            lotto_id,
+            channel,
+            original_sender,
+            entropy,
+            vote,
+            payout,
         ),
-        ExecuteMsg::BuyTicket { lotto_id } => execute_buy_ticket(deps, env, info, lotto_id),
-        ExecuteMsg::NoisReceive { callback } => execute_receive(deps, env, info, callback),
+        ExecuteMsg::NoisReceive { callback } => {
+            execute_receive(deps, env, info, ProviderCallback::Nois(callback))
+        }
+        #[cfg(any(test, feature = "mock"))]
+        ExecuteMsg::ReceiveMockRandomness { job_id } => {
+            execute_receive(deps, env, info, ProviderCallback::Mock { job_id })
+        }
+        ExecuteMsg::Reveal { lotto_id, secret } => {
+            execute_reveal(deps, env, info, lotto_id, secret)
+        }
+        ExecuteMsg::Settle { lotto_id, limit } => execute_settle(deps, env, lotto_id, limit),
+        ExecuteMsg::RefundLotto { lotto_id, limit } => {
+            execute_refund_lotto(deps, env, lotto_id, limit)
+        }
         ExecuteMsg::SetConfig {
             nois_proxy,
             manager,
@@ -99,6 +233,12 @@ pub fn execute(
             protocol_commission_percent,
             creator_commission_percent,
             is_paused,
+            randomness_source,
+            max_duration_seconds,
+            allowed_denoms,
+            community_pool_mode,
+            swap_router,
+            reserve_pays_nois_fee,
         } => execute_set_config(
             deps,
             info,
@@ -109,21 +249,65 @@ pub fn execute(
             protocol_commission_percent,
             creator_commission_percent,
             is_paused,
+            randomness_source,
+            max_duration_seconds,
+            allowed_denoms,
+            community_pool_mode,
+            swap_router,
+            reserve_pays_nois_fee,
         ),
         ExecuteMsg::WithdrawAll { address, denom } => {
-            execute_withdraw_all(deps, info, address, denom)
+            execute_withdraw_all(deps, env, info, address, denom)
+        }
+        ExecuteMsg::Withdraw { recipient, coins } => {
+            execute_withdraw(deps, env, info, recipient, coins)
+        }
+        ExecuteMsg::WithdrawAllDenoms { recipient } => {
+            execute_withdraw_all_denoms(deps, env, info, recipient)
+        }
+        ExecuteMsg::SetRevenueRecipients { recipients } => {
+            execute_set_revenue_recipients(deps, info, recipients)
+        }
+        ExecuteMsg::DistributeRevenue {} => execute_distribute_revenue(deps, env, info),
+        ExecuteMsg::ConvertRevenue { denom, min_out } => {
+            execute_convert_revenue(deps, env, info, denom, min_out)
+        }
+        ExecuteMsg::AddHook { contract } => execute_add_hook(deps, info, contract),
+        ExecuteMsg::RemoveHook { contract } => execute_remove_hook(deps, info, contract),
+        ExecuteMsg::SetPayoutChannel {
+            lotto_id,
+            channel,
+            remote_address,
+        } => execute_set_payout_channel(deps, info, lotto_id, channel, remote_address),
+        ExecuteMsg::RemovePayoutChannel { lotto_id } => {
+            execute_remove_payout_channel(deps, info, lotto_id)
+        }
+        ExecuteMsg::Claim { address } => execute_claim(deps, env, info, address),
+        ExecuteMsg::FundNoisFeeReserve { amount } => {
+            execute_fund_nois_fee_reserve(deps, info, amount)
+        }
+        ExecuteMsg::AddBeneficiary { address, name } => {
+            execute_add_beneficiary(deps, info, address, name)
+        }
+        ExecuteMsg::RemoveBeneficiary { address } => {
+            execute_remove_beneficiary(deps, info, address)
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn execute_create_lotto(
-    deps: DepsMut,
+    mut deps: DepsMut,
     env: Env,
     info: MessageInfo,
     ticket_price: Coin,
     duration_seconds: u64,
     number_of_winners: u32,
     community_pool_percentage: u32,
+    commitment: Option<HexBinary>,
+    beneficiary: Option<String>,
+    vote_weighting: Option<VoteWeighting>,
+    series: Option<String>,
 ) -> Result<Response, ContractError> {
     // validate Timestamp
     let mut config = CONFIG.load(deps.storage)?;
@@ -133,6 +317,17 @@ fn execute_create_lotto(
         return Err(ContractError::ContractIsPaused {});
     };
 
+    if duration_seconds > config.max_duration_seconds {
+        return Err(ContractError::DurationTooLong {
+            max_duration_seconds: config.max_duration_seconds,
+        });
+    }
+    if !config.allowed_denoms.is_empty() && !config.allowed_denoms.contains(&ticket_price.denom) {
+        return Err(ContractError::DenomNotAllowed {
+            denom: ticket_price.denom,
+        });
+    }
+
     let expiration = env.block.time.plus_seconds(duration_seconds);
 
     if config.protocol_commission_percent
@@ -142,6 +337,81 @@ fn execute_create_lotto(
     {
         return Err(ContractError::IncorrectRates {});
     }
+    // Without winners nobody would own the prize
+    if number_of_winners == 0 {
+        return Err(ContractError::NoWinners);
+    }
+
+    let beneficiary = match beneficiary {
+        Some(beneficiary) => {
+            let beneficiary = deps.api.addr_validate(&beneficiary)?;
+            if !BENEFICIARIES.has(deps.storage, &beneficiary) {
+                return Err(ContractError::BeneficiaryNotRegistered {
+                    address: beneficiary.into_string(),
+                });
+            }
+            Some(beneficiary)
+        }
+        None => None,
+    };
+
+    let commit_reveal = match commitment {
+        Some(commitment) if commitment.len() != 32 => return Err(ContractError::InvalidCommitment),
+        Some(commitment) => Some(CommitReveal {
+            commitment,
+            entropy: HexBinary::default(),
+            reveal_deadline: expiration.plus_seconds(REVEAL_PERIOD_SECONDS),
+            secret: None,
+        }),
+        None => None,
+    };
+
+    let randomness_source = config.randomness_source.clone();
+    let randomness_provider = provider(&config, &randomness_source);
+
+    // The provider is paid exactly its price, either by the creator or out of the fee reserve
+    let mut refund_msgs: Vec<CosmosMsg> = vec![];
+    let nois_fee = match info.funds.as_slice() {
+        [] if randomness_provider.is_free() => Coin::new(0, ""),
+        [] if config.reserve_pays_nois_fee => {
+            let (fee, reserve) = reserve_nois_fee(deps.as_ref(), randomness_provider.as_ref())?
+                .ok_or(ContractError::NoFundsProvided)?;
+            let remaining = reserve - fee.amount;
+            if remaining.is_zero() {
+                NOIS_FEE_RESERVE.remove(deps.storage, fee.denom.clone());
+            } else {
+                NOIS_FEE_RESERVE.save(deps.storage, fee.denom.clone(), &remaining)?;
+            }
+            release_escrow(deps.storage, &fee.denom, fee.amount)?;
+            fee
+        }
+        [] => return Err(ContractError::NoFundsProvided),
+        [payment] => {
+            let price = randomness_provider
+                .price(deps.as_ref(), &payment.denom)?
+                .ok_or_else(|| ContractError::UnsupportedNoisFeeDenom {
+                    denom: payment.denom.clone(),
+                })?;
+            if payment.amount < price {
+                return Err(ContractError::InsufficientNoisFee {
+                    required: Coin::new(price.u128(), payment.denom.clone()),
+                });
+            }
+            // Send back whatever the creator paid on top of the proxy price
+            let excess = payment.amount - price;
+            if !excess.is_zero() {
+                refund_msgs.push(
+                    BankMsg::Send {
+                        to_address: info.sender.to_string(),
+                        amount: vec![Coin::new(excess.u128(), payment.denom.clone())],
+                    }
+                    .into(),
+                );
+            }
+            Coin::new(price.u128(), payment.denom.clone())
+        }
+        _ => return Err(ContractError::InvalidPayment),
+    };
 
     let lotto = Lotto {
         nonce,
@@ -153,30 +423,170 @@ fn execute_create_lotto(
         creator: info.sender,
         number_of_winners,
         community_pool_percentage,
+        status: LottoStatus::Open,
+        commit_reveal,
+        draw: None,
+        settlement: None,
+        tickets_refunded: 0,
+        beneficiary,
+        vote_weighting,
+        randomness_source,
+        series,
     };
 
     LOTTOS.save(deps.storage, nonce, &lotto)?;
+    update_stats(deps.storage, &lotto.creator, |stats| {
+        stats.lottos_created += 1
+    })?;
 
-    let msg = WasmMsg::Execute {
-        contract_addr: config.clone().nois_proxy.into_string(),
-        // GetRandomnessAfter requests the randomness from the proxy after a specific timestamp
-        // The job id is needed to know what randomness we are referring to upon reception in the callback.
-        msg: to_binary(&ProxyExecuteMsg::GetRandomnessAfter {
-            after: expiration,
-            job_id: "lotto-".to_string() + nonce.to_string().as_str(),
-        })?,
-        // We pay here the proxy contract with whatever the depositors sends. The depositor needs to check in advance the proxy prices.
-        funds: info.funds, // Just pass on all funds we got
-    };
+    // The beacon of a commit-reveal lotto is published after the reveal deadline, so that the
+    // creator doesn't know the outcome when deciding to reveal
+    let randomness_after = lotto
+        .commit_reveal
+        .as_ref()
+        .map_or(expiration, |commit_reveal| commit_reveal.reveal_deadline);
+    let msgs = randomness_provider.request(
+        "lotto-".to_string() + nonce.to_string().as_str(),
+        randomness_after,
+        // Bank sends of zero coins are rejected, so a free randomness request is sent without funds
+        if nois_fee.amount.is_zero() {
+            vec![]
+        } else {
+            vec![nois_fee.clone()]
+        },
+    )?;
+    let event = events::lotto_created(&lotto, &nois_fee);
+    let hooks = hook_msgs(
+        deps.storage,
+        LottoHook::LottoCreated {
+            lotto_id: nonce,
+            creator: lotto.creator.to_string(),
+            ticket_price: lotto.ticket_price.clone(),
+            expiration,
+        },
+    )?;
+    let (subscription_msgs, subscription_events) =
+        buy_subscribed_tickets(deps.branch(), &env, &lotto)?;
     nonce += 1;
     config.lotto_nonce = nonce;
     CONFIG.save(deps.storage, &config)?;
 
     // save config
     Ok(Response::new()
-        .add_message(msg)
+        .add_messages(msgs)
+        .add_messages(refund_msgs)
+        .add_submessages(hooks)
+        .add_submessages(subscription_msgs)
+        .add_event(event)
+        .add_events(subscription_events)
         .add_attribute("action", "create_lotto")
-        .add_attribute("next_nonce", nonce.to_string()))
+        .add_attribute("lotto_id", lotto.nonce.to_string())
+        .add_attribute("nois_fee", nois_fee.to_string()))
+}
+
+// Buys the tickets of the subscriptions matching a new lotto. Only a bounded number of
+// subscriptions is checked per lotto, starting after the last one checked for the previous
+// lotto, so that every subscription gets its turn. Subscriptions whose balance can't pay for
+// a ticket end, so that they don't take the turn of funded ones.
+fn buy_subscribed_tickets(
+    mut deps: DepsMut,
+    env: &Env,
+    lotto: &Lotto,
+) -> Result<(Vec<SubMsg>, Vec<Event>), ContractError> {
+    let mut msgs = vec![];
+    let mut events = vec![];
+    if env.block.time >= lotto.expiration {
+        return Ok((msgs, events));
+    }
+
+    let cursor = SUBSCRIPTION_CURSOR.may_load(deps.storage)?;
+    let mut subscriptions = SUBSCRIPTIONS
+        .range(
+            deps.storage,
+            cursor.as_ref().map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(MAX_SUBSCRIPTIONS_PER_LOTTO)
+        .collect::<StdResult<Vec<_>>>()?;
+    // Wrap around to the subscriptions up to the cursor
+    if let Some(cursor) = &cursor {
+        let remaining = MAX_SUBSCRIPTIONS_PER_LOTTO - subscriptions.len();
+        subscriptions.extend(
+            SUBSCRIPTIONS
+                .range(
+                    deps.storage,
+                    None,
+                    Some(Bound::inclusive(cursor)),
+                    Order::Ascending,
+                )
+                .take(remaining)
+                .collect::<StdResult<Vec<_>>>()?,
+        );
+    }
+    if let Some((last, _)) = subscriptions.last() {
+        SUBSCRIPTION_CURSOR.save(deps.storage, last)?;
+    }
+
+    let ticket_price = &lotto.ticket_price;
+    for (subscriber, mut subscription) in subscriptions {
+        if !subscription.matches(lotto) {
+            continue;
+        }
+        let spent = subscription.spent;
+        let mut unfunded = false;
+        for _ in 0..subscription.tickets_per_lotto {
+            if subscription.spent + ticket_price.amount > subscription.max_spend.amount {
+                break;
+            }
+            match debit_player_balance(deps.storage, &subscriber, ticket_price) {
+                Ok(()) => {}
+                Err(ContractError::InsufficientBalance { .. }) => {
+                    unfunded = true;
+                    break;
+                }
+                Err(err) => return Err(err),
+            }
+            let res = execute_buy_ticket(
+                deps.branch(),
+                env.clone(),
+                subscriber.clone(),
+                vec![ticket_price.clone()],
+                lotto.nonce,
+                None,
+                None,
+            )?;
+            msgs.extend(res.messages);
+            events.extend(res.events);
+            subscription.spent += ticket_price.amount;
+        }
+        if unfunded || subscription.spent >= subscription.max_spend.amount {
+            SUBSCRIPTIONS.remove(deps.storage, &subscriber);
+        } else if subscription.spent != spent {
+            SUBSCRIPTIONS.save(deps.storage, &subscriber, &subscription)?;
+        }
+    }
+    Ok((msgs, events))
+}
+
+// Returns the price in the first reserve denom accepted by the provider that can cover it,
+// along with the reserve in that denom
+fn reserve_nois_fee(
+    deps: Deps,
+    randomness_provider: &dyn RandomnessProvider,
+) -> Result<Option<(Coin, Uint128)>, ContractError> {
+    let reserves = NOIS_FEE_RESERVE
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (denom, reserve) in reserves {
+        match randomness_provider.price(deps, &denom)? {
+            Some(price) if reserve >= price => {
+                return Ok(Some((Coin::new(price.u128(), denom), reserve)))
+            }
+            _ => continue,
+        }
+    }
+    Ok(None)
 }
 
 fn validate_payment(deposit: &Coin, funds: &[Coin]) -> Result<(), ContractError> {
@@ -204,6 +614,12 @@ fn execute_set_config(
     protocol_commission_percent: Option<u32>,
     creator_commission_percent: Option<u32>,
     is_paused: Option<bool>,
+    randomness_source: Option<RandomnessSource>,
+    max_duration_seconds: Option<u64>,
+    allowed_denoms: Option<Vec<String>>,
+    community_pool_mode: Option<CommunityPoolMode>,
+    swap_router: Option<SwapRouterMsg>,
+    reserve_pays_nois_fee: Option<bool>,
 ) -> Result<Response, ContractError> {
     let config = CONFIG.load(deps.storage)?;
     ensure_eq!(info.sender, config.manager, ContractError::Unauthorized);
@@ -227,6 +643,17 @@ fn execute_set_config(
         creator_commission_percent.unwrap_or(config.creator_commission_percent);
 
     let is_paused = is_paused.unwrap_or(config.is_paused);
+    let randomness_source = randomness_source.unwrap_or(config.randomness_source);
+    let max_duration_seconds = max_duration_seconds.unwrap_or(config.max_duration_seconds);
+    let allowed_denoms = allowed_denoms.unwrap_or(config.allowed_denoms);
+    let community_pool_mode = community_pool_mode.unwrap_or(config.community_pool_mode);
+    validate_community_pool_mode(&community_pool_mode)?;
+    let swap_router = match swap_router {
+        Some(SwapRouterMsg::Set { address }) => Some(deps.api.addr_validate(&address)?),
+        Some(SwapRouterMsg::Clear {}) => None,
+        None => config.swap_router,
+    };
+    let reserve_pays_nois_fee = reserve_pays_nois_fee.unwrap_or(config.reserve_pays_nois_fee);
 
     // TODO Check that the commissions are less than 100% and that the new values don't mess up with currently running lottos
 
@@ -238,18 +665,29 @@ fn execute_set_config(
         protocol_commission_percent,
         creator_commission_percent,
         is_paused,
+        randomness_source,
+        max_duration_seconds,
+        allowed_denoms,
+        community_pool_mode,
+        swap_router,
+        reserve_pays_nois_fee,
     };
 
     CONFIG.save(deps.storage, &new_config)?;
 
-    Ok(Response::default().add_attribute("action", "set_config"))
+    Ok(Response::default()
+        .add_event(events::config_updated(&new_config))
+        .add_attribute("action", "set_config"))
 }
 
 fn execute_buy_ticket(
     deps: DepsMut,
     env: Env,
-    info: MessageInfo,
+    buyer: Addr,
+    funds: Vec<Coin>,
     lotto_id: u64,
+    entropy: Option<HexBinary>,
+    vote: Option<String>,
 ) -> Result<Response, ContractError> {
     if !LOTTOS.has(deps.storage, lotto_id) {
         return Err(ContractError::LottoNotFound {});
@@ -259,404 +697,6022 @@ fn execute_buy_ticket(
     let ticket_price = lotto.clone().ticket_price;
 
     // Not sure the best way to go about validating the coin
-    validate_payment(&ticket_price, info.funds.as_slice())?;
+    validate_payment(&ticket_price, funds.as_slice())?;
 
     // Check if lotto is active
     if env.block.time >= lotto.expiration {
         return Err(ContractError::LottoDepositStageEnded {});
     }
+    if entropy.is_some() && lotto.commit_reveal.is_none() {
+        return Err(ContractError::NotCommitReveal);
+    }
     // Increment total deposit
-    let balance: Coin = info
-        .funds
+    let balance: Coin = funds
         .iter()
-        .filter(|coin| coin.denom == ticket_price.denom)
-        .last()
+        .rfind(|coin| coin.denom == ticket_price.denom)
         .unwrap()
         .clone();
 
     lotto.balance += balance.amount;
+    add_escrow(deps.storage, &balance.denom, balance.amount)?;
     // Add participant address
-    lotto.participants.push(info.clone().sender);
+    lotto.participants.push(buyer.clone());
+
+    // Each participant makes the commit-reveal randomness unpredictable to the creator
+    if let Some(commit_reveal) = lotto.commit_reveal.as_mut() {
+        let mut hasher = Sha256::new();
+        hasher.update(commit_reveal.entropy.as_slice());
+        hasher.update(buyer.as_bytes());
+        if let Some(entropy) = entropy {
+            hasher.update(entropy.as_slice());
+        }
+        commit_reveal.entropy = hasher.finalize().to_vec().into();
+    }
+
+    if let Some(vote) = vote {
+        let weighting = lotto
+            .vote_weighting
+            .clone()
+            .ok_or(ContractError::VotingDisabled)?;
+        let beneficiary = deps.api.addr_validate(&vote)?;
+        if !BENEFICIARIES.has(deps.storage, &beneficiary) {
+            return Err(ContractError::BeneficiaryNotRegistered {
+                address: beneficiary.into_string(),
+            });
+        }
+        let tickets = VOTES
+            .may_load(deps.storage, (lotto_id, &beneficiary, &buyer))?
+            .unwrap_or_default();
+        VOTES.save(
+            deps.storage,
+            (lotto_id, &beneficiary, &buyer),
+            &(tickets + 1),
+        )?;
+        VOTE_TALLIES.update(
+            deps.storage,
+            (lotto_id, &beneficiary),
+            |tally| -> StdResult<_> {
+                let mut tally = tally.unwrap_or_default();
+                tally.tickets += 1;
+                if tickets == 0 {
+                    tally.voters += 1;
+                }
+                // The weight of the voter grows from sqrt(tickets) to sqrt(tickets + 1), the
+                // steps add up to exactly sqrt(n) for n tickets
+                tally.weight += match weighting {
+                    VoteWeighting::Linear => Decimal::one(),
+                    VoteWeighting::Quadratic => {
+                        Decimal::from_ratio(tickets + 1, 1u128).sqrt()
+                            - Decimal::from_ratio(tickets, 1u128).sqrt()
+                    }
+                };
+                Ok(tally)
+            },
+        )?;
+    }
 
     // Save the state & updated config escrow balance
     LOTTOS.save(deps.storage, lotto_id, &lotto)?;
+    update_stats(deps.storage, &lotto.creator, |stats| {
+        stats.tickets_sold += 1;
+        add_coin(&mut stats.volume, &ticket_price);
+    })?;
+    TICKETS.update(deps.storage, (&buyer, lotto_id), |record| -> StdResult<_> {
+        let mut record = record.unwrap_or(TicketsRecord {
+            tickets: 0,
+            denom: ticket_price.denom.clone(),
+        });
+        record.tickets += 1;
+        Ok(record)
+    })?;
+
+    let hooks = hook_msgs(
+        deps.storage,
+        LottoHook::TicketBought {
+            lotto_id,
+            buyer: buyer.to_string(),
+            ticket_price,
+        },
+    )?;
 
     Ok(Response::new()
+        .add_submessages(hooks)
+        .add_event(events::ticket_purchased(&lotto, &buyer))
         .add_attribute("action", "participate")
-        .add_attribute("sender", info.sender.as_ref())
+        .add_attribute("sender", buyer.as_ref())
         .add_attribute("new_balance", lotto.balance.to_string()))
 }
 
-pub fn execute_receive(
+fn execute_buy_ticket_with_signature(
     deps: DepsMut,
-    _env: Env,
-    info: MessageInfo,
-    callback: NoisCallback,
+    env: Env,
+    lotto_id: u64,
+    buyer_pubkey: Binary,
+    signature: Binary,
+    nonce: u64,
+    deadline: Timestamp,
 ) -> Result<Response, ContractError> {
-    let config = CONFIG.load(deps.storage)?;
+    if env.block.time > deadline {
+        return Err(ContractError::AuthorizationExpired);
+    }
+    let authorization = TicketAuthorization {
+        contract: env.contract.address.to_string(),
+        chain_id: env.block.chain_id.clone(),
+        lotto_id,
+        nonce,
+        deadline,
+    };
+    verify_authorization(deps.as_ref(), &authorization, &buyer_pubkey, &signature)?;
+    let buyer = pubkey_address(&env.contract.address, &buyer_pubkey)?;
 
-    // callback should only be allowed to be called by the proxy contract
-    // otherwise anyone can cut the randomness workflow and cheat the randomness by sending the randomness directly to this contract
-    ensure_eq!(
-        info.sender,
-        config.nois_proxy,
-        ContractError::UnauthorizedReceive
-    );
-    let randomness: [u8; 32] = callback
-        .randomness
-        .to_array()
-        .map_err(|_| ContractError::InvalidRandomness)?;
+    // Every authorization can be used once
+    let expected = NONCES.may_load(deps.storage, &buyer)?.unwrap_or_default();
+    if nonce != expected {
+        return Err(ContractError::InvalidNonce { expected });
+    }
+    NONCES.save(deps.storage, &buyer, &(expected + 1))?;
 
-    // extract lotto nonce
-    let job_id = callback.job_id;
-    let lotto_nonce: u64 = job_id
-        .strip_prefix("lotto-")
-        .expect("Strange, how is the job-id not prefixed with lotto-")
-        .parse()
-        .unwrap(); //Needs to check that the received nonce is a number
+    execute_buy_ticket_from_balance(deps, env, buyer, lotto_id, None, None)
+}
 
-    // Make sure the lotto nonce is valid
-    let lotto = LOTTOS.load(deps.storage, lotto_nonce)?;
-    assert!(lotto.winners.is_none(), "Strange, there's already winners");
-    let participants = lotto.participants;
+fn execute_buy_ticket_from_balance(
+    deps: DepsMut,
+    env: Env,
+    buyer: Addr,
+    lotto_id: u64,
+    entropy: Option<HexBinary>,
+    vote: Option<String>,
+) -> Result<Response, ContractError> {
+    let ticket_price = LOTTOS
+        .may_load(deps.storage, lotto_id)?
+        .ok_or(ContractError::LottoNotFound)?
+        .ticket_price;
+    debit_player_balance(deps.storage, &buyer, &ticket_price)?;
 
-    let winners = nois::pick(
-        randomness,
-        lotto.number_of_winners as usize,
-        participants.clone(),
-    );
+    execute_buy_ticket(
+        deps,
+        env,
+        buyer,
+        vec![ticket_price],
+        lotto_id,
+        entropy,
+        vote,
+    )
+}
 
-    if winners.is_empty() {
-        return Err(ContractError::NoDepositors {});
+fn execute_deposit_balance(
+    deps: DepsMut,
+    info: MessageInfo,
+    recipient: Option<String>,
+) -> Result<Response, ContractError> {
+    let recipient = match recipient {
+        Some(recipient) => deps.api.addr_validate(&recipient)?,
+        None => info.sender,
+    };
+    if info.funds.is_empty() {
+        return Err(ContractError::NoFundsProvided);
+    }
+    for coin in &info.funds {
+        credit_player_balance(deps.storage, &recipient, coin)?;
     }
 
-    let amount_creator = get_percentage(lotto.balance, config.creator_commission_percent);
-    let amount_protocol = get_percentage(lotto.balance, config.protocol_commission_percent);
-    let amount_community_pool = get_percentage(lotto.balance, lotto.community_pool_percentage);
+    Ok(Response::new()
+        .add_attribute("action", "deposit_balance")
+        .add_attribute("recipient", recipient)
+        .add_attribute("amount", coins_string(&info.funds)))
+}
 
-    let prize_amount = lotto.balance - (amount_protocol + amount_creator + amount_community_pool);
-    let amount_winner = prize_amount.multiply_ratio(
-        Uint128::new(1),
-        Uint128::new(lotto.number_of_winners as u128),
-    );
+fn execute_withdraw_balance(
+    deps: DepsMut,
+    info: MessageInfo,
+    coins: Vec<Coin>,
+) -> Result<Response, ContractError> {
+    let amounts = withdrawal_amounts(&coins)?;
+    for coin in &amounts {
+        debit_player_balance(deps.storage, &info.sender, coin)?;
+    }
 
-    let denom = lotto.ticket_price.clone().denom;
+    Ok(Response::new()
+        .add_message(BankMsg::Send {
+            to_address: info.sender.to_string(),
+            amount: amounts.clone(),
+        })
+        .add_attribute("action", "withdraw_balance")
+        .add_attribute("sender", info.sender)
+        .add_attribute("amount", coins_string(&amounts)))
+}
 
-    let mut msgs = vec![
-        // Community Pool
-        BankMsg::Send {
-            to_address: config.community_pool.clone().into_string(),
-            amount: vec![Coin {
-                amount: amount_community_pool,
-                denom: denom.clone(),
-            }],
-        },
-        // creator
-        BankMsg::Send {
-            to_address: lotto.creator.clone().into_string(),
-            amount: vec![Coin {
-                amount: amount_creator,
-                denom: denom.clone(),
-            }],
-        },
-    ];
-    for winner in winners.clone() {
-        msgs.push(
-            // Winner
-            BankMsg::Send {
-                to_address: winner.clone().into_string(),
-                amount: vec![Coin {
-                    amount: amount_winner,
-                    denom: denom.clone(),
-                }],
-            },
-        );
+fn execute_set_credit_winnings(
+    deps: DepsMut,
+    info: MessageInfo,
+    enabled: bool,
+) -> Result<Response, ContractError> {
+    if enabled {
+        CREDIT_WINNINGS.save(deps.storage, &info.sender, &Empty {})?;
+    } else {
+        CREDIT_WINNINGS.remove(deps.storage, &info.sender);
     }
 
-    // Update Lotto Data
-    let new_lotto = Lotto {
-        nonce: lotto_nonce,
-        ticket_price: lotto.ticket_price,
-        balance: lotto.balance,
-        expiration: lotto.expiration,
-        participants,
-        winners: Some(winners.clone()),
-        creator: lotto.creator,
-        number_of_winners: lotto.number_of_winners,
-        community_pool_percentage: lotto.community_pool_percentage,
-    };
+    Ok(Response::new()
+        .add_attribute("action", "set_credit_winnings")
+        .add_attribute("enabled", enabled.to_string()))
+}
 
-    // Increment protocol amount
-    let protocol_balances = PROTOCOL_BALANCES.may_load(deps.storage, denom.clone())?;
-    match protocol_balances {
-        Some(pb) => PROTOCOL_BALANCES.save(deps.storage, denom.clone(), &(pb + amount_protocol))?,
-        None => PROTOCOL_BALANCES.save(deps.storage, denom.clone(), &amount_protocol)?,
-    };
-
-    LOTTOS.save(deps.storage, lotto_nonce, &new_lotto)?;
-
-    // msgs.push(CosmosMsg::Stargate {
-    //     type_url: "/cosmos.distribution.v1beta1.MsgFundCommunityPool".to_string(),
-    //     value: encode_msg_fund_community_pool(
-    //         &Coin {
-    //             denom: denom.clone(),
-    //             amount: amount_community_pool,
-    //         },
-    //         &env.contract.address,
-    //     )
-    //     .into(),
-    // });
-
-    Ok(Response::new().add_messages(msgs).add_attributes(vec![
-        Attribute::new("action", "receive-randomness-and-send-prize"),
-        Attribute::new("job_id", job_id),
-        Attribute::new(
-            "winner_send_amount",
-            Coin {
-                amount: amount_winner,
-                denom,
-            }
-            .to_string(),
-        ), // actual send amount
-    ]))
+fn execute_subscribe(
+    deps: DepsMut,
+    info: MessageInfo,
+    criteria: SubscriptionCriteriaMsg,
+    tickets_per_lotto: u32,
+    max_spend: Coin,
+) -> Result<Response, ContractError> {
+    if tickets_per_lotto == 0 || tickets_per_lotto > MAX_TICKETS_PER_SUBSCRIPTION {
+        return Err(ContractError::InvalidTicketsPerLotto {
+            max: MAX_TICKETS_PER_SUBSCRIPTION,
+        });
+    }
+    if max_spend.amount.is_zero() {
+        return Err(ContractError::ZeroSpendingCap);
+    }
+    if matches!(&criteria.denom, Some(denom) if *denom != max_spend.denom) {
+        return Err(ContractError::SubscriptionDenomMismatch);
+    }
+    if criteria.series.is_some() && criteria.creator.is_none() {
+        return Err(ContractError::SeriesWithoutCreator);
+    }
+    if !PLAYER_BALANCES.has(deps.storage, (&info.sender, &max_spend.denom)) {
+        return Err(ContractError::UnfundedSubscription {
+            denom: max_spend.denom,
+        });
+    }
+    let creator = criteria
+        .creator
+        .map(|creator| deps.api.addr_validate(&creator))
+        .transpose()?;
+
+    SUBSCRIPTIONS.save(
+        deps.storage,
+        &info.sender,
+        &Subscription {
+            criteria: SubscriptionCriteria {
+                creator,
+                denom: criteria.denom,
+                max_price: criteria.max_price,
+                series: criteria.series,
+            },
+            tickets_per_lotto,
+            max_spend: max_spend.clone(),
+            spent: Uint128::zero(),
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "subscribe")
+        .add_attribute("subscriber", info.sender)
+        .add_attribute("tickets_per_lotto", tickets_per_lotto.to_string())
+        .add_attribute("max_spend", max_spend.to_string()))
 }
 
-fn get_percentage(amount: Uint128, ratio: u32) -> Uint128 {
-    amount.mul_floor((ratio as u128, 100))
+fn execute_unsubscribe(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    if !SUBSCRIPTIONS.has(deps.storage, &info.sender) {
+        return Err(ContractError::SubscriptionNotFound);
+    }
+    SUBSCRIPTIONS.remove(deps.storage, &info.sender);
+
+    Ok(Response::new()
+        .add_attribute("action", "unsubscribe")
+        .add_attribute("subscriber", info.sender))
 }
 
-fn execute_withdraw_all(
+#[allow(clippy::too_many_arguments)]
+fn execute_buy_ticket_ibc(
     deps: DepsMut,
+    env: Env,
     info: MessageInfo,
-    to_address: String,
-    denom: String,
+    lotto_id: u64,
+    channel: String,
+    original_sender: String,
+    entropy: Option<HexBinary>,
+    vote: Option<String>,
+    payout: CrossChainPayoutMsg,
 ) -> Result<Response, ContractError> {
-    // TODO CRITICAL! Make sure not to withdraw current deposits that have not been settled
-    // Keep a state of the manager revenue
+    // Anyone can put this message in a memo, only the funds of the IBC-hooks account prove
+    // that the transfer came from `original_sender`
+    if info.sender.as_str() != ibc_hooks_sender(&env.contract.address, &channel, &original_sender)?
+    {
+        return Err(ContractError::InvalidIbcHooksSender);
+    }
+    // A counterparty chain can claim any sender, one of this chain would be paid back over IBC
+    // in place of the local account
+    if is_local_address(&env.contract.address, &original_sender) {
+        return Err(ContractError::LocalOriginalSender { original_sender });
+    }
+    let payout = match payout {
+        CrossChainPayoutMsg::Payback {} => CrossChainPayout::Payback,
+        CrossChainPayoutMsg::Local { address } => {
+            CrossChainPayout::Local(deps.api.addr_validate(&address)?)
+        }
+    };
+    // The IBC-hooks account is derived from the channel and the original sender, so it is an
+    // identity no local account or other remote sender can take over
+    let buyer = info.sender;
+    let participant = CrossChainParticipant {
+        channel,
+        original_sender,
+        payout,
+    };
+    match CROSS_CHAIN_PARTICIPANTS.may_load(deps.storage, (lotto_id, &buyer))? {
+        Some(existing) if existing != participant => {
+            return Err(ContractError::CrossChainPayoutMismatch)
+        }
+        Some(_) => {}
+        None => CROSS_CHAIN_PARTICIPANTS.save(deps.storage, (lotto_id, &buyer), &participant)?,
+    }
+    // The account is derived from the channel and the original sender, so it always maps to
+    // the same ones
+    if !CROSS_CHAIN_ACCOUNTS.has(deps.storage, &buyer) {
+        CROSS_CHAIN_ACCOUNTS.save(
+            deps.storage,
+            &buyer,
+            &PayoutChannel {
+                channel: participant.channel,
+                remote_address: participant.original_sender.clone(),
+            },
+        )?;
+    }
 
-    let config = CONFIG.load(deps.storage)?;
+    let res = execute_buy_ticket(deps, env, buyer, info.funds, lotto_id, entropy, vote)?;
+    Ok(res.add_attribute("original_sender", participant.original_sender))
+}
 
-    // check the calling address is the authorised address
-    ensure_eq!(info.sender, config.manager, ContractError::Unauthorized);
+pub fn execute_receive(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    callback: ProviderCallback,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    // extract lotto nonce
+    let lotto_nonce: u64 = callback
+        .job_id()
+        .strip_prefix("lotto-")
+        .and_then(|nonce| nonce.parse().ok())
+        .ok_or_else(|| ContractError::InvalidJobId {
+            job_id: callback.job_id().to_string(),
+        })?;
 
-    let payable_amount: Uint128;
+    // Make sure the lotto nonce is valid
+    let lotto = LOTTOS
+        .may_load(deps.storage, lotto_nonce)?
+        .ok_or(ContractError::LottoNotFound)?;
+    // The provider the lotto booked its randomness from makes sure the callback comes from it,
+    // whatever source is configured by now
+    let ready = provider(&config, &lotto.randomness_source).receive(&env, &info, callback)?;
+    if let Some(commit_reveal) = &lotto.commit_reveal {
+        // Neither the creator nor the participants may know the beacon while they can act
+        if ready.published <= commit_reveal.reveal_deadline {
+            return Err(ContractError::RandomnessTooEarly);
+        }
+    }
+    match draw_randomness(&lotto, &ready.randomness) {
+        Some(randomness) => on_randomness_ready(deps, lotto, ready, randomness),
+        // Not revealed in time, the participants get their tickets back
+        None => on_reveal_missed(deps, lotto),
+    }
+}
 
-    let protocol_balance = PROTOCOL_BALANCES.may_load(deps.storage, denom.clone())?;
-    if let Some(pb) = protocol_balance {
-        payable_amount = pb;
-    } else {
-        return Err(ContractError::ProtocolBalanceDoesNotOwnSuchDenom {
-            denom: denom.clone(),
-        });
+// The randomness the winners are picked with. Commit-reveal lottos mix the secret of the
+// creator, the entropy of the participants and the beacon, each of them unknown to whoever
+// controls the others while the lotto is open. None if the secret wasn't revealed.
+fn draw_randomness(lotto: &Lotto, beacon: &[u8; 32]) -> Option<[u8; 32]> {
+    let commit_reveal = match &lotto.commit_reveal {
+        Some(commit_reveal) => commit_reveal,
+        None => return Some(*beacon),
     };
+    let secret = commit_reveal.secret.as_ref()?;
+    Some(
+        Sha256::new()
+            .chain_update(secret.as_slice())
+            .chain_update(commit_reveal.entropy.as_slice())
+            .chain_update(beacon)
+            .finalize()
+            .into(),
+    )
+}
 
-    let payable_balance: Coin = Coin::new(u128::from(payable_amount), denom.clone());
-    PROTOCOL_BALANCES.save(deps.storage, denom.clone(), &Uint128::zero())?;
+fn participants_hash(participants: &[Addr]) -> StdResult<HexBinary> {
+    Ok(Sha256::digest(to_binary(participants)?.as_slice())
+        .to_vec()
+        .into())
+}
 
-    let msg = BankMsg::Send {
-        to_address,
-        amount: vec![payable_balance.clone()],
-    };
+fn execute_reveal(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    lotto_id: u64,
+    secret: HexBinary,
+) -> Result<Response, ContractError> {
+    let mut lotto = LOTTOS
+        .may_load(deps.storage, lotto_id)?
+        .ok_or(ContractError::LottoNotFound)?;
+    ensure_eq!(info.sender, lotto.creator, ContractError::Unauthorized);
+    let commit_reveal = lotto
+        .commit_reveal
+        .as_mut()
+        .ok_or(ContractError::NotCommitReveal)?;
 
-    let res = Response::new()
-        .add_message(msg)
-        .add_attribute("action", "withdraw_all")
-        .add_attribute("amount", payable_balance.to_string());
-    Ok(res)
+    if lotto.status != LottoStatus::Open {
+        return Err(ContractError::LottoClosed);
+    }
+    if env.block.time <= lotto.expiration {
+        return Err(ContractError::LottoNotExpired);
+    }
+    if env.block.time > commit_reveal.reveal_deadline {
+        return Err(ContractError::RevealDeadlinePassed);
+    }
+    if Sha256::digest(secret.as_slice()).as_slice() != commit_reveal.commitment.as_slice() {
+        return Err(ContractError::InvalidReveal);
+    }
+
+    commit_reveal.secret = Some(secret);
+    LOTTOS.save(deps.storage, lotto_id, &lotto)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "reveal")
+        .add_attribute("lotto_id", lotto_id.to_string()))
 }
 
-// For chains that have a community pool module, you can use this function.
-// Neutron has a community pool built as a cosmwasm contract
-// fn encode_msg_fund_community_pool(amount: &Coin, depositor: &Addr) -> Vec<u8> {
-//     // Coin: https://github.com/cosmos/cosmos-sdk/blob/v0.45.15/proto/cosmos/base/v1beta1/coin.proto#L14-L19
-//     // MsgFundCommunityPool: https://github.com/cosmos/cosmos-sdk/blob/v0.45.15/proto/cosmos/distribution/v1beta1/tx.proto#L69-L76
-//     let coin = Anybuf::new()
-//         .append_string(1, &amount.denom)
-//         .append_string(2, amount.amount.to_string());
-//     Anybuf::new()
-//         .append_message(1, &coin)
-//         .append_string(2, depositor)
-//         .into_vec()
-// }
+fn execute_refund_lotto(
+    deps: DepsMut,
+    env: Env,
+    lotto_id: u64,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let mut lotto = LOTTOS
+        .may_load(deps.storage, lotto_id)?
+        .ok_or(ContractError::LottoNotFound)?;
+    let commit_reveal = lotto
+        .commit_reveal
+        .as_ref()
+        .ok_or(ContractError::NotCommitReveal)?;
 
-#[entry_point]
-pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<QueryResponse> {
-    let response = match msg {
-        QueryMsg::Lotto { lotto_nonce } => to_binary(&query_lotto(deps, env, lotto_nonce)?)?,
-        QueryMsg::ProtocolBalances {} => to_binary(&query_protocol_balances(deps)?)?,
-        QueryMsg::LottosDesc {
-            creator,
-            is_active,
-            start_after,
-            limit,
-        } => to_binary(&query_lottos(
-            deps,
-            env,
-            creator,
-            is_active,
-            start_after,
-            limit,
-            Order::Descending,
-        )?)?,
-        QueryMsg::LottosAsc {
-            creator,
-            is_active,
-            start_after,
-            limit,
-        } => to_binary(&query_lottos(
-            deps,
-            env,
-            creator,
-            is_active,
-            start_after,
-            limit,
-            Order::Ascending,
-        )?)?,
-        QueryMsg::Config {} => to_binary(&query_config(deps)?)?,
-    };
-    Ok(response)
+    match lotto.status {
+        // Anyone can start the refund without waiting for the beacon
+        LottoStatus::Open => {
+            if env.block.time <= commit_reveal.reveal_deadline {
+                return Err(ContractError::RevealDeadlineNotPassed);
+            }
+            if commit_reveal.secret.is_some() {
+                return Err(ContractError::SecretRevealed);
+            }
+            start_refund(deps.storage, &mut lotto)?;
+        }
+        LottoStatus::Refunding => {}
+        LottoStatus::Settling | LottoStatus::Settled | LottoStatus::Refunded => {
+            return Err(ContractError::LottoClosed)
+        }
+    }
+
+    // Participants holding several tickets of the batch get a single transfer
+    let limit = limit.unwrap_or(DEFAULT_SETTLE_LIMIT) as usize;
+    let mut refunds: BTreeMap<&Addr, Uint128> = BTreeMap::new();
+    let mut tickets = 0;
+    for participant in lotto
+        .participants
+        .iter()
+        .skip(lotto.tickets_refunded as usize)
+        .take(limit)
+    {
+        *refunds.entry(participant).or_default() += lotto.ticket_price.amount;
+        tickets += 1;
+    }
+    let mut msgs = vec![];
+    let mut refunded = Uint128::zero();
+    for (participant, amount) in refunds {
+        msgs.extend(payout_msg(
+            deps.storage,
+            &env,
+            Some(lotto_id),
+            participant,
+            Coin::new(amount.u128(), lotto.ticket_price.denom.clone()),
+        )?);
+        refunded += amount;
+    }
+    release_escrow(deps.storage, &lotto.ticket_price.denom, refunded)?;
+
+    lotto.tickets_refunded += tickets;
+    if lotto.tickets_refunded as usize == lotto.participants.len() {
+        lotto.status = LottoStatus::Refunded;
+    }
+    LOTTOS.save(deps.storage, lotto_id, &lotto)?;
+
+    Ok(Response::new()
+        .add_submessages(msgs)
+        .add_attribute("action", "refund_lotto")
+        .add_attribute("lotto_id", lotto_id.to_string())
+        .add_attribute("tickets_refunded", lotto.tickets_refunded.to_string()))
 }
 
-fn query_lotto(deps: Deps, env: Env, nonce: u64) -> StdResult<LottoResponse> {
-    let lotto = LOTTOS.load(deps.storage, nonce)?;
-    let winners = lotto
-        .winners
-        .map(|winners| winners.iter().map(|wn| wn.clone().into_string()).collect());
-    let is_expired = env.block.time > lotto.expiration;
-    Ok(LottoResponse {
-        nonce: lotto.nonce,
-        ticket_price: lotto.ticket_price,
-        balance: lotto.balance,
-        participants: lotto
-            .participants
-            .iter()
-            .map(|dep| dep.to_string())
-            .collect(),
-        winners,
-        is_expired,
-        expiration: lotto.expiration,
-        creator: lotto.creator.to_string(),
-        number_of_winners: lotto.number_of_winners,
-        community_pool_percentage: lotto.community_pool_percentage,
-    })
+// Marks a lotto whose secret wasn't revealed in time for refunding. The participants are paid
+// back by `RefundLotto` so that the beacon callback never runs out of gas, as for `Settle`.
+fn on_reveal_missed(deps: DepsMut, mut lotto: Lotto) -> Result<Response, ContractError> {
+    if lotto.status != LottoStatus::Open {
+        return Err(ContractError::LottoClosed);
+    }
+    start_refund(deps.storage, &mut lotto)?;
+    LOTTOS.save(deps.storage, lotto.nonce, &lotto)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "start_refund")
+        .add_attribute("lotto_id", lotto.nonce.to_string()))
 }
 
-fn query_lottos(
-    deps: Deps,
+fn start_refund(storage: &mut dyn Storage, lotto: &mut Lotto) -> StdResult<()> {
+    // Refunded tickets were never sold, the lotto itself still counts as created
+    update_stats(storage, &lotto.creator, |stats| {
+        stats.tickets_sold -= lotto.participants.len() as u64;
+        sub_coin(
+            &mut stats.volume,
+            &Coin::new(lotto.balance.u128(), lotto.ticket_price.denom.clone()),
+        );
+    })?;
+    lotto.status = LottoStatus::Refunding;
+    Ok(())
+}
+
+// Stores the randomness of a lotto once it is available.
+// Nothing else happens here so that a callback never runs out of gas and loses the randomness,
+// the winners are drawn and paid by `Settle`.
+fn on_randomness_ready(
+    deps: DepsMut,
+    mut lotto: Lotto,
+    ready: RandomnessReady,
+    randomness: [u8; 32],
+) -> Result<Response, ContractError> {
+    let RandomnessReady {
+        job_id,
+        randomness: beacon,
+        published,
+    } = ready;
+
+    if lotto.status != LottoStatus::Open {
+        return Err(ContractError::LottoClosed);
+    }
+    // Randomness known while tickets can still be bought would let players game the draw
+    if published <= lotto.expiration {
+        return Err(ContractError::RandomnessTooEarly);
+    }
+
+    // The participants can't change anymore, the hash lets anyone check the draw was made
+    // from the list stored on the lotto
+    lotto.draw = Some(DrawProof {
+        randomness: randomness.into(),
+        beacon: beacon.into(),
+        published,
+        participants_hash: participants_hash(&lotto.participants)?,
+    });
+    lotto.status = LottoStatus::Settling;
+    LOTTOS.save(deps.storage, lotto.nonce, &lotto)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "receive-randomness")
+        .add_attribute("job_id", job_id))
+}
+
+fn execute_settle(
+    deps: DepsMut,
     env: Env,
-    creator: Option<String>,
-    is_active: Option<bool>,
-    start_after: Option<u64>,
-    limit: Option<u64>,
-    order: Order,
-) -> StdResult<LottosResponse> {
-    let limit: usize = limit.unwrap_or(100) as usize;
-    let (low_bound, top_bound) = match order {
-        Order::Ascending => (start_after.map(Bound::exclusive), None),
-        Order::Descending => (None, start_after.map(Bound::exclusive)),
+    lotto_id: u64,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let mut lotto = LOTTOS
+        .may_load(deps.storage, lotto_id)?
+        .ok_or(ContractError::LottoNotFound)?;
+    match lotto.status {
+        LottoStatus::Open => return Err(ContractError::RandomnessNotReceived),
+        LottoStatus::Settled | LottoStatus::Refunding | LottoStatus::Refunded => {
+            return Err(ContractError::LottoClosed)
+        }
+        LottoStatus::Settling => {}
+    }
+    let draw = lotto
+        .draw
+        .clone()
+        .ok_or(ContractError::RandomnessNotReceived)?;
+    let denom = lotto.ticket_price.clone().denom;
+    let mut msgs: Vec<CosmosMsg> = vec![];
+
+    // The first batch draws the winners and pays the commissions
+    let mut settlement = match lotto.settlement.clone() {
+        Some(settlement) => settlement,
+        None => {
+            let winners = nois::pick(
+                draw.randomness.to_array()?,
+                winners_count(&lotto),
+                lotto.participants.clone(),
+            );
+
+            let Payout {
+                amount_community_pool,
+                amount_creator,
+                amount_protocol,
+                amount_winner,
+            } = payout(&config, &lotto);
+
+            // Without participants there is nothing to pay
+            if !winners.is_empty() {
+                msgs.extend(community_share_msgs(
+                    deps.as_ref(),
+                    &config,
+                    &env,
+                    &lotto,
+                    Coin {
+                        amount: amount_community_pool,
+                        denom: denom.clone(),
+                    },
+                )?);
+                // Bank sends of zero coins are rejected
+                if !amount_creator.is_zero() {
+                    msgs.push(
+                        // creator
+                        BankMsg::Send {
+                            to_address: lotto.creator.clone().into_string(),
+                            amount: vec![Coin {
+                                amount: amount_creator,
+                                denom: denom.clone(),
+                            }],
+                        }
+                        .into(),
+                    );
+                }
+
+                // Increment protocol amount
+                PROTOCOL_BALANCES.update(
+                    deps.storage,
+                    denom.clone(),
+                    |balance| -> StdResult<_> { Ok(balance.unwrap_or_default() + amount_protocol) },
+                )?;
+                update_stats(deps.storage, &lotto.creator, |stats| {
+                    add_coin(
+                        &mut stats.creator_commissions,
+                        &Coin::new(amount_creator.u128(), denom.clone()),
+                    );
+                    add_coin(
+                        &mut stats.community_pool_contributions,
+                        &Coin::new(amount_community_pool.u128(), denom.clone()),
+                    );
+                    add_coin(
+                        &mut stats.protocol_revenue,
+                        &Coin::new(amount_protocol.u128(), denom.clone()),
+                    );
+                })?;
+            }
+            // The protocol commission stays owed as protocol balance and the prizes until they
+            // are paid. The commissions sent out and the rounding dust are no longer owed.
+            let prizes = amount_winner * Uint128::new(winners.len() as u128);
+            release_escrow(
+                deps.storage,
+                &denom,
+                lotto.balance - amount_protocol - prizes,
+            )?;
+
+            lotto.winners = Some(winners);
+            Settlement {
+                amount_winner,
+                winners_paid: 0,
+                amount_creator,
+                amount_protocol,
+                amount_community_pool,
+            }
+        }
     };
-    let lottos: Vec<LottoResponse> = LOTTOS
-        .range(deps.storage, low_bound, top_bound, order)
-        .filter(|l| {
-            if let Some(creator) = &creator {
-                l.as_ref().unwrap().1.creator.as_ref() == creator
-            } else {
-                true
+
+    let winners = lotto.winners.clone().unwrap_or_default();
+    let limit = limit.unwrap_or(DEFAULT_SETTLE_LIMIT) as usize;
+    let mut payouts = vec![];
+    let mut prizes_paid = Uint128::zero();
+    for winner in winners
+        .iter()
+        .skip(settlement.winners_paid as usize)
+        .take(limit)
+    {
+        // Winner, a prize rounded down to zero can't be sent
+        if !settlement.amount_winner.is_zero() {
+            payouts.extend(payout_msg(
+                deps.storage,
+                &env,
+                Some(lotto_id),
+                winner,
+                Coin::new(settlement.amount_winner.u128(), denom.clone()),
+            )?);
+        }
+        // The same address can win several times
+        WINNINGS.update(deps.storage, (winner, lotto_id), |record| -> StdResult<_> {
+            let mut record = record.unwrap_or(WinningsRecord {
+                amount_won: Uint128::zero(),
+                denom: denom.clone(),
+            });
+            record.amount_won += settlement.amount_winner;
+            Ok(record)
+        })?;
+        settlement.winners_paid += 1;
+        prizes_paid += settlement.amount_winner;
+    }
+    update_stats(deps.storage, &lotto.creator, |stats| {
+        add_coin(
+            &mut stats.prizes_paid,
+            &Coin::new(prizes_paid.u128(), denom.clone()),
+        )
+    })?;
+    release_escrow(deps.storage, &denom, prizes_paid)?;
+
+    let mut hooks = vec![];
+    let mut settled_events = vec![];
+    if settlement.winners_paid as usize == winners.len() {
+        lotto.status = LottoStatus::Settled;
+        settled_events.push(events::lotto_settled(
+            &lotto,
+            settlement.amount_winner,
+            &Commissions {
+                creator: settlement.amount_creator,
+                protocol: settlement.amount_protocol,
+                community_pool: settlement.amount_community_pool,
+            },
+        ));
+        hooks = hook_msgs(
+            deps.storage,
+            LottoHook::LottoSettled {
+                lotto_id,
+                winners: winners.iter().map(Addr::to_string).collect(),
+                prize_per_winner: Coin::new(settlement.amount_winner.u128(), denom.clone()),
+            },
+        )?;
+    }
+    let winners_paid = settlement.winners_paid;
+    let amount_winner = settlement.amount_winner;
+    lotto.settlement = Some(settlement);
+    LOTTOS.save(deps.storage, lotto_id, &lotto)?;
+
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_submessages(payouts)
+        .add_submessages(hooks)
+        .add_events(settled_events)
+        .add_attributes(vec![
+            Attribute::new("action", "settle"),
+            Attribute::new("lotto_id", lotto_id.to_string()),
+            Attribute::new(
+                "winner_send_amount",
+                Coin {
+                    amount: amount_winner,
+                    denom,
+                }
+                .to_string(),
+            ), // actual send amount
+            Attribute::new("winners_paid", winners_paid.to_string()),
+        ]))
+}
+
+// How the balance of a lotto is split when it settles
+struct Payout {
+    amount_community_pool: Uint128,
+    amount_creator: Uint128,
+    amount_protocol: Uint128,
+    // Paid to each of the winners
+    amount_winner: Uint128,
+}
+
+fn payout(config: &Config, lotto: &Lotto) -> Payout {
+    let amount_creator = get_percentage(lotto.balance, config.creator_commission_percent);
+    let amount_protocol = get_percentage(lotto.balance, config.protocol_commission_percent);
+    let amount_community_pool = get_percentage(lotto.balance, lotto.community_pool_percentage);
+
+    let prize_amount = lotto.balance - (amount_protocol + amount_creator + amount_community_pool);
+    // With fewer tickets than winners the prize is shared by the tickets sold
+    let amount_winner = match winners_count(lotto) {
+        0 => Uint128::zero(),
+        winners => prize_amount.multiply_ratio(Uint128::new(1), Uint128::new(winners as u128)),
+    };
+    Payout {
+        amount_community_pool,
+        amount_creator,
+        amount_protocol,
+        amount_winner,
+    }
+}
+
+// Every ticket wins at most once
+fn winners_count(lotto: &Lotto) -> usize {
+    (lotto.number_of_winners as usize).min(lotto.participants.len())
+}
+
+fn get_percentage(amount: Uint128, ratio: u32) -> Uint128 {
+    amount.mul_floor((ratio as u128, 100))
+}
+
+// Applies the same change to the protocol wide and to the creator's statistics
+fn update_stats(
+    storage: &mut dyn Storage,
+    creator: &Addr,
+    update: impl Fn(&mut Stats),
+) -> StdResult<()> {
+    let mut stats = STATS.may_load(storage)?.unwrap_or_default();
+    update(&mut stats);
+    STATS.save(storage, &stats)?;
+    CREATOR_STATS.update(storage, creator, |stats| -> StdResult<_> {
+        let mut stats = stats.unwrap_or_default();
+        update(&mut stats);
+        Ok(stats)
+    })?;
+    Ok(())
+}
+
+// Adds a coin to a list holding at most one entry per denom
+fn add_coin(coins: &mut Vec<Coin>, coin: &Coin) {
+    if coin.amount.is_zero() {
+        return;
+    }
+    match coins.iter_mut().find(|c| c.denom == coin.denom) {
+        Some(existing) => existing.amount += coin.amount,
+        None => coins.push(coin.clone()),
+    }
+}
+
+// Merges the coins to withdraw per denom, since a bank send rejects zero amounts and repeated
+// denoms
+fn withdrawal_amounts(coins: &[Coin]) -> Result<Vec<Coin>, ContractError> {
+    let mut amounts = vec![];
+    for coin in coins {
+        add_coin(&mut amounts, coin);
+    }
+    if amounts.is_empty() {
+        return Err(ContractError::NothingToWithdraw);
+    }
+    Ok(amounts)
+}
+
+fn sub_coin(coins: &mut Vec<Coin>, coin: &Coin) {
+    if let Some(existing) = coins.iter_mut().find(|c| c.denom == coin.denom) {
+        existing.amount -= coin.amount;
+    }
+    coins.retain(|c| !c.amount.is_zero());
+}
+
+fn execute_withdraw_all(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    to_address: String,
+    denom: String,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+
+    // check the calling address is the authorised address
+    ensure_eq!(info.sender, config.manager, ContractError::Unauthorized);
+    let recipient = deps.api.addr_validate(&to_address)?;
+
+    let payable_amount = PROTOCOL_BALANCES
+        .may_load(deps.storage, denom.clone())?
+        .filter(|balance| !balance.is_zero())
+        .ok_or_else(|| ContractError::ProtocolBalanceDoesNotOwnSuchDenom {
+            denom: denom.clone(),
+        })?;
+    let payable_balance = Coin::new(payable_amount.u128(), denom);
+    let (msg, event) = withdraw_protocol_balances(
+        deps,
+        &env,
+        info.sender,
+        recipient,
+        vec![payable_balance.clone()],
+    )?;
+
+    let res = Response::new()
+        .add_message(msg)
+        .add_event(event)
+        .add_attribute("action", "withdraw_all")
+        .add_attribute("amount", payable_balance.to_string());
+    Ok(res)
+}
+
+fn execute_withdraw(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    recipient: String,
+    coins: Vec<Coin>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_eq!(info.sender, config.manager, ContractError::Unauthorized);
+    let recipient = deps.api.addr_validate(&recipient)?;
+
+    let amounts = withdrawal_amounts(&coins)?;
+    let amount = coins_string(&amounts);
+    let (msg, event) =
+        withdraw_protocol_balances(deps, &env, info.sender, recipient.clone(), amounts)?;
+
+    Ok(Response::new()
+        .add_message(msg)
+        .add_event(event)
+        .add_attribute("action", "withdraw")
+        .add_attribute("recipient", recipient)
+        .add_attribute("amount", amount))
+}
+
+fn execute_withdraw_all_denoms(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    recipient: String,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_eq!(info.sender, config.manager, ContractError::Unauthorized);
+    let recipient = deps.api.addr_validate(&recipient)?;
+
+    let amounts = PROTOCOL_BALANCES
+        .range(deps.storage, None, None, Order::Ascending)
+        .filter(|item| !matches!(item, Ok((_, balance)) if balance.is_zero()))
+        .map(|item| item.map(|(denom, balance)| Coin::new(balance.u128(), denom)))
+        .collect::<StdResult<Vec<_>>>()?;
+    if amounts.is_empty() {
+        return Err(ContractError::NothingToWithdraw);
+    }
+    let amount = coins_string(&amounts);
+    let (msg, event) =
+        withdraw_protocol_balances(deps, &env, info.sender, recipient.clone(), amounts)?;
+
+    Ok(Response::new()
+        .add_message(msg)
+        .add_event(event)
+        .add_attribute("action", "withdraw_all_denoms")
+        .add_attribute("recipient", recipient)
+        .add_attribute("amount", amount))
+}
+
+fn execute_set_revenue_recipients(
+    deps: DepsMut,
+    info: MessageInfo,
+    recipients: Vec<RevenueRecipientMsg>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_eq!(info.sender, config.manager, ContractError::Unauthorized);
+
+    let recipients = recipients
+        .into_iter()
+        .map(|recipient| {
+            if recipient.weight == 0 {
+                return Err(ContractError::InvalidRevenueWeight);
             }
+            let destination = match recipient.destination {
+                RevenueDestinationMsg::Address { address } => {
+                    RevenueDestination::Address(deps.api.addr_validate(&address)?)
+                }
+                RevenueDestinationMsg::NoisFeeReserve {} => RevenueDestination::NoisFeeReserve,
+            };
+            Ok(RevenueRecipient {
+                destination,
+                weight: recipient.weight,
+            })
         })
-        .filter(|l| {
-            if let Some(is_active) = &is_active {
-                (l.as_ref().unwrap().1.expiration > env.block.time) == *is_active
-            } else {
-                true
+        .collect::<Result<Vec<_>, ContractError>>()?;
+    REVENUE_RECIPIENTS.save(deps.storage, &recipients)?;
+
+    Ok(Response::new().add_attribute("action", "set_revenue_recipients"))
+}
+
+fn execute_distribute_revenue(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let recipients = REVENUE_RECIPIENTS
+        .may_load(deps.storage)?
+        .unwrap_or_default();
+    if recipients.is_empty() {
+        return Err(ContractError::NoRevenueRecipients);
+    }
+    let total_weight: u128 = recipients.iter().map(|r| r.weight as u128).sum();
+    let balances = PROTOCOL_BALANCES
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    // Shares of every recipient, in the order of the recipients
+    let mut shares: Vec<Vec<Coin>> = vec![vec![]; recipients.len()];
+    for (denom, balance) in balances {
+        for (share, recipient) in shares.iter_mut().zip(&recipients) {
+            let amount = balance.multiply_ratio(recipient.weight as u128, total_weight);
+            if !amount.is_zero() {
+                share.push(Coin::new(amount.u128(), denom.clone()));
             }
-        })
-        .take(limit)
-        .map(|c| {
-            c.map(|(nonce, lotto)| {
-                let winners = lotto
-                    .winners
-                    .map(|winners| winners.iter().map(|wn| wn.clone().into_string()).collect());
-
-                LottoResponse {
-                    ticket_price: lotto.ticket_price,
-                    balance: lotto.balance,
-                    participants: lotto
-                        .participants
-                        .iter()
-                        .map(|dep| dep.to_string())
-                        .collect(),
-                    expiration: lotto.expiration,
-                    winners,
-                    nonce,
-                    creator: lotto.creator.to_string(),
-                    number_of_winners: lotto.number_of_winners,
-                    community_pool_percentage: lotto.community_pool_percentage,
-                    is_expired: env.block.time > lotto.expiration,
+        }
+    }
+    if shares.iter().all(Vec::is_empty) {
+        return Err(ContractError::NothingToDistribute);
+    }
+
+    let mut msgs = vec![];
+    let mut withdrawal_events = vec![];
+    for (recipient, share) in recipients.into_iter().zip(shares) {
+        if share.is_empty() {
+            continue;
+        }
+        match recipient.destination {
+            // Paid out like a withdrawal, so escrowed funds stay untouched
+            RevenueDestination::Address(address) => {
+                let (msg, event) = withdraw_protocol_balances(
+                    deps.branch(),
+                    &env,
+                    info.sender.clone(),
+                    address,
+                    share,
+                )?;
+                msgs.push(msg);
+                withdrawal_events.push(event);
+            }
+            // Stays in the contract and in the escrow
+            RevenueDestination::NoisFeeReserve => {
+                for coin in share {
+                    PROTOCOL_BALANCES.update(
+                        deps.storage,
+                        coin.denom.clone(),
+                        |balance| -> StdResult<_> {
+                            Ok(balance.unwrap_or_default().checked_sub(coin.amount)?)
+                        },
+                    )?;
+                    NOIS_FEE_RESERVE.update(
+                        deps.storage,
+                        coin.denom.clone(),
+                        |reserve| -> StdResult<_> { Ok(reserve.unwrap_or_default() + coin.amount) },
+                    )?;
+                }
+            }
+        }
+    }
+
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_events(withdrawal_events)
+        .add_attribute("action", "distribute_revenue"))
+}
+
+/// Takes `coins` out of the protocol balances, records the withdrawal in the audit log and
+/// returns the transfer to `recipient`
+fn withdraw_protocol_balances(
+    deps: DepsMut,
+    env: &Env,
+    initiator: Addr,
+    recipient: Addr,
+    coins: Vec<Coin>,
+) -> Result<(BankMsg, Event), ContractError> {
+    for coin in &coins {
+        let protocol_balance = PROTOCOL_BALANCES
+            .may_load(deps.storage, coin.denom.clone())?
+            .unwrap_or_default();
+        if protocol_balance < coin.amount {
+            return Err(ContractError::InsufficientProtocolBalance {
+                denom: coin.denom.clone(),
+            });
+        }
+        ensure_not_escrowed(deps.as_ref(), env, coin)?;
+
+        let remaining = protocol_balance - coin.amount;
+        if remaining.is_zero() {
+            PROTOCOL_BALANCES.remove(deps.storage, coin.denom.clone());
+        } else {
+            PROTOCOL_BALANCES.save(deps.storage, coin.denom.clone(), &remaining)?;
+        }
+        release_escrow(deps.storage, &coin.denom, coin.amount)?;
+    }
+
+    let id = WITHDRAWAL_COUNT.may_load(deps.storage)?.unwrap_or_default();
+    WITHDRAWAL_COUNT.save(deps.storage, &(id + 1))?;
+    let withdrawal = Withdrawal {
+        initiator,
+        recipient: recipient.clone(),
+        coins: coins.clone(),
+        time: env.block.time,
+        height: env.block.height,
+    };
+    WITHDRAWALS.save(deps.storage, id, &withdrawal)?;
+
+    Ok((
+        BankMsg::Send {
+            to_address: recipient.into_string(),
+            amount: coins,
+        },
+        events::withdrawal(id, &withdrawal),
+    ))
+}
+
+/// Fails unless `coin` can leave the contract while everything else escrowed stays covered
+fn ensure_not_escrowed(deps: Deps, env: &Env, coin: &Coin) -> Result<(), ContractError> {
+    let balance = deps
+        .querier
+        .query_balance(&env.contract.address, coin.denom.clone())?
+        .amount;
+    let escrowed = ESCROW
+        .may_load(deps.storage, coin.denom.clone())?
+        .unwrap_or_default();
+    if balance < escrowed.max(coin.amount) {
+        return Err(ContractError::EscrowedFunds {
+            denom: coin.denom.clone(),
+        });
+    }
+    Ok(())
+}
+
+fn execute_convert_revenue(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    denom: String,
+    min_out: Coin,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_eq!(info.sender, config.manager, ContractError::Unauthorized);
+    let swap_router = config.swap_router.ok_or(ContractError::NoSwapRouter)?;
+    if denom == min_out.denom {
+        return Err(ContractError::InvalidConversion { denom });
+    }
+
+    let amount = PROTOCOL_BALANCES
+        .may_load(deps.storage, denom.clone())?
+        .filter(|balance| !balance.is_zero())
+        .ok_or_else(|| ContractError::ProtocolBalanceDoesNotOwnSuchDenom {
+            denom: denom.clone(),
+        })?;
+    let offer = Coin::new(amount.u128(), denom.clone());
+    ensure_not_escrowed(deps.as_ref(), &env, &offer)?;
+    PROTOCOL_BALANCES.remove(deps.storage, denom.clone());
+    release_escrow(deps.storage, &denom, amount)?;
+
+    // The output is measured in the reply, so the router doesn't need to return any data
+    let balance_before = deps
+        .querier
+        .query_balance(&env.contract.address, min_out.denom.clone())?
+        .amount;
+    PENDING_CONVERSION.save(
+        deps.storage,
+        &PendingConversion {
+            offer: offer.clone(),
+            min_out: min_out.clone(),
+            balance_before,
+        },
+    )?;
+    let swap = WasmMsg::Execute {
+        contract_addr: swap_router.into_string(),
+        msg: to_binary(&SwapRouterExecuteMsg::Swap {
+            ask_denom: min_out.denom,
+            min_out: min_out.amount,
+        })?,
+        funds: vec![offer.clone()],
+    };
+
+    Ok(Response::new()
+        .add_submessage(SubMsg::reply_on_success(swap, CONVERT_REVENUE_REPLY_ID))
+        .add_attribute("action", "convert_revenue")
+        .add_attribute("offer", offer.to_string()))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> Result<Response, ContractError> {
+    match msg.id {
+        CONVERT_REVENUE_REPLY_ID => reply_convert_revenue(deps, env),
+        // Swallow the error so that the lotto goes on without the hook
+        HOOK_REPLY_ID => Ok(Response::new()
+            .add_attribute("action", "hook_failed")
+            .add_attribute("error", msg.result.unwrap_err())),
+        IBC_PAYOUT_REPLY_ID => reply_ibc_payout(deps, msg.result),
+        id => Err(ContractError::UnknownReplyId { id }),
+    }
+}
+
+fn reply_ibc_payout(deps: DepsMut, result: SubMsgResult) -> Result<Response, ContractError> {
+    let payout = IBC_PAYOUTS_SENT
+        .pop_front(deps.storage)?
+        .ok_or(ContractError::UnexpectedIbcPayoutReply)?;
+    match result {
+        SubMsgResult::Ok(response) => match response.data.as_deref().and_then(transfer_sequence) {
+            // Wait for the acknowledgement of the packet
+            Some(sequence) => {
+                IBC_PAYOUTS.save(deps.storage, (&payout.channel, sequence), &payout)?;
+                Ok(Response::new()
+                    .add_attribute("action", "ibc_payout")
+                    .add_attribute("channel", payout.channel)
+                    .add_attribute("sequence", sequence.to_string()))
+            }
+            // The callbacks can't be matched to the payout without its sequence. Failing would
+            // revert the settlement or refund it is part of, so the transfer goes on untracked.
+            // Its funds come back as surplus if it fails, counted in the solvency query.
+            None => {
+                UNTRACKED_PAYOUTS.update(
+                    deps.storage,
+                    payout.coin.denom.clone(),
+                    |untracked| -> StdResult<_> {
+                        Ok(untracked.unwrap_or_default() + payout.coin.amount)
+                    },
+                )?;
+                Ok(Response::new()
+                    .add_attribute("action", "ibc_payout_untracked")
+                    .add_attribute("owner", payout.owner)
+                    .add_attribute("channel", payout.channel)
+                    .add_attribute("amount", payout.coin.to_string()))
+            }
+        },
+        // The transfer couldn't even be sent, e.g. because the channel doesn't exist
+        SubMsgResult::Err(error) => {
+            credit_claimable(deps.storage, &payout.owner, &payout.coin)?;
+            Ok(Response::new()
+                .add_attribute("action", "ibc_payout_failed")
+                .add_attribute("owner", payout.owner)
+                .add_attribute("amount", payout.coin.to_string())
+                .add_attribute("error", error))
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn sudo(deps: DepsMut, _env: Env, msg: SudoMsg) -> Result<Response, ContractError> {
+    match msg {
+        SudoMsg::Response { request, .. } => sudo_ibc_payout(deps, request, None),
+        SudoMsg::Error { request, details } => sudo_ibc_payout(deps, request, Some(details)),
+        SudoMsg::Timeout { request } => sudo_ibc_payout(deps, request, Some("timeout".to_string())),
+    }
+}
+
+// Settles an ICS20 payout once its packet is acknowledged or timed out.
+// The funds of a failed transfer are back in the contract and can be claimed by the owner.
+fn sudo_ibc_payout(
+    deps: DepsMut,
+    request: RequestPacket,
+    error: Option<String>,
+) -> Result<Response, ContractError> {
+    let (channel, sequence) = match (request.source_channel, request.sequence) {
+        (Some(channel), Some(sequence)) => (channel, sequence),
+        _ => return Ok(Response::new()),
+    };
+    // Not a payout, e.g. a transfer to the Nois proxy
+    let payout = match IBC_PAYOUTS.may_load(deps.storage, (&channel, sequence))? {
+        Some(payout) => payout,
+        None => return Ok(Response::new()),
+    };
+    IBC_PAYOUTS.remove(deps.storage, (&channel, sequence));
+
+    let error = match error {
+        Some(error) => error,
+        None => {
+            return Ok(Response::new()
+                .add_attribute("action", "ibc_payout_acknowledged")
+                .add_attribute("channel", channel)
+                .add_attribute("sequence", sequence.to_string()))
+        }
+    };
+    credit_claimable(deps.storage, &payout.owner, &payout.coin)?;
+    Ok(Response::new()
+        .add_attribute("action", "ibc_payout_failed")
+        .add_attribute("owner", payout.owner)
+        .add_attribute("amount", payout.coin.to_string())
+        .add_attribute("error", error))
+}
+
+fn reply_convert_revenue(deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+    let PendingConversion {
+        offer,
+        min_out,
+        balance_before,
+    } = PENDING_CONVERSION.load(deps.storage)?;
+    PENDING_CONVERSION.remove(deps.storage);
+
+    let balance_after = deps
+        .querier
+        .query_balance(&env.contract.address, min_out.denom.clone())?
+        .amount;
+    let received = balance_after.saturating_sub(balance_before);
+    if received < min_out.amount {
+        return Err(ContractError::SwapOutputTooLow { min_out });
+    }
+    PROTOCOL_BALANCES.update(
+        deps.storage,
+        min_out.denom.clone(),
+        |balance| -> StdResult<_> { Ok(balance.unwrap_or_default() + received) },
+    )?;
+    add_escrow(deps.storage, &min_out.denom, received)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "convert_revenue_reply")
+        .add_attribute("offer", offer.to_string())
+        .add_attribute(
+            "received",
+            Coin::new(received.u128(), min_out.denom).to_string(),
+        ))
+}
+
+fn execute_fund_nois_fee_reserve(
+    deps: DepsMut,
+    info: MessageInfo,
+    amount: Coin,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_eq!(info.sender, config.manager, ContractError::Unauthorized);
+
+    let protocol_balance = PROTOCOL_BALANCES
+        .may_load(deps.storage, amount.denom.clone())?
+        .ok_or_else(|| ContractError::ProtocolBalanceDoesNotOwnSuchDenom {
+            denom: amount.denom.clone(),
+        })?;
+    if protocol_balance < amount.amount {
+        return Err(ContractError::InsufficientProtocolBalance {
+            denom: amount.denom,
+        });
+    }
+
+    let remaining = protocol_balance - amount.amount;
+    if remaining.is_zero() {
+        PROTOCOL_BALANCES.remove(deps.storage, amount.denom.clone());
+    } else {
+        PROTOCOL_BALANCES.save(deps.storage, amount.denom.clone(), &remaining)?;
+    }
+    NOIS_FEE_RESERVE.update(
+        deps.storage,
+        amount.denom.clone(),
+        |reserve| -> StdResult<_> { Ok(reserve.unwrap_or_default() + amount.amount) },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "fund_nois_fee_reserve")
+        .add_attribute("amount", amount.to_string()))
+}
+
+fn execute_add_hook(
+    deps: DepsMut,
+    info: MessageInfo,
+    contract: String,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_eq!(info.sender, config.manager, ContractError::Unauthorized);
+
+    let contract = deps.api.addr_validate(&contract)?;
+    HOOKS.save(deps.storage, &contract, &Empty {})?;
+
+    Ok(Response::new()
+        .add_attribute("action", "add_hook")
+        .add_attribute("contract", contract))
+}
+
+fn execute_remove_hook(
+    deps: DepsMut,
+    info: MessageInfo,
+    contract: String,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_eq!(info.sender, config.manager, ContractError::Unauthorized);
+
+    let contract = deps.api.addr_validate(&contract)?;
+    if !HOOKS.has(deps.storage, &contract) {
+        return Err(ContractError::HookNotRegistered {
+            contract: contract.into_string(),
+        });
+    }
+    HOOKS.remove(deps.storage, &contract);
+
+    Ok(Response::new()
+        .add_attribute("action", "remove_hook")
+        .add_attribute("contract", contract))
+}
+
+fn execute_set_payout_channel(
+    deps: DepsMut,
+    info: MessageInfo,
+    lotto_id: Option<u64>,
+    channel: String,
+    remote_address: String,
+) -> Result<Response, ContractError> {
+    if !channel.starts_with("channel-") || remote_address.is_empty() {
+        return Err(ContractError::InvalidPayoutChannel { channel });
+    }
+    let payout_channel = PayoutChannel {
+        channel,
+        remote_address,
+    };
+    match lotto_id {
+        Some(lotto_id) => {
+            if !LOTTOS.has(deps.storage, lotto_id) {
+                return Err(ContractError::LottoNotFound);
+            }
+            LOTTO_PAYOUT_CHANNELS.save(deps.storage, (&info.sender, lotto_id), &payout_channel)?
+        }
+        None => PAYOUT_CHANNELS.save(deps.storage, &info.sender, &payout_channel)?,
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set_payout_channel")
+        .add_attribute("channel", payout_channel.channel)
+        .add_attribute("remote_address", payout_channel.remote_address))
+}
+
+fn execute_remove_payout_channel(
+    deps: DepsMut,
+    info: MessageInfo,
+    lotto_id: Option<u64>,
+) -> Result<Response, ContractError> {
+    match lotto_id {
+        Some(lotto_id) => {
+            let key = (&info.sender, lotto_id);
+            if !LOTTO_PAYOUT_CHANNELS.has(deps.storage, key) {
+                return Err(ContractError::NoPayoutChannel);
+            }
+            LOTTO_PAYOUT_CHANNELS.remove(deps.storage, key);
+        }
+        None => {
+            if !PAYOUT_CHANNELS.has(deps.storage, &info.sender) {
+                return Err(ContractError::NoPayoutChannel);
+            }
+            PAYOUT_CHANNELS.remove(deps.storage, &info.sender);
+        }
+    }
+
+    Ok(Response::new().add_attribute("action", "remove_payout_channel"))
+}
+
+fn execute_claim(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    address: Option<String>,
+) -> Result<Response, ContractError> {
+    // Cross-chain participants own their payouts through their IBC-hooks account, so every
+    // owner is an address of this chain
+    let owner = match address {
+        Some(address) => deps.api.addr_validate(&address)?,
+        None => info.sender,
+    };
+    let claimable = CLAIMABLE
+        .prefix(&owner)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, amount)| Coin::new(amount.u128(), denom)))
+        .collect::<StdResult<Vec<_>>>()?;
+    if claimable.is_empty() {
+        return Err(ContractError::NothingToClaim);
+    }
+
+    let mut msgs = vec![];
+    for coin in &claimable {
+        CLAIMABLE.remove(deps.storage, (&owner, &coin.denom));
+        release_escrow(deps.storage, &coin.denom, coin.amount)?;
+        msgs.extend(payout_msg(deps.storage, &env, None, &owner, coin.clone())?);
+    }
+
+    Ok(Response::new()
+        .add_submessages(msgs)
+        .add_attribute("action", "claim")
+        .add_attribute("owner", owner)
+        .add_attribute("amount", coins_string(&claimable)))
+}
+
+fn execute_add_beneficiary(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+    name: String,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_eq!(info.sender, config.manager, ContractError::Unauthorized);
+
+    let address = deps.api.addr_validate(&address)?;
+    BENEFICIARIES.save(deps.storage, &address, &Beneficiary { name })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "add_beneficiary")
+        .add_attribute("address", address))
+}
+
+fn execute_remove_beneficiary(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_eq!(info.sender, config.manager, ContractError::Unauthorized);
+
+    let address = deps.api.addr_validate(&address)?;
+    if !BENEFICIARIES.has(deps.storage, &address) {
+        return Err(ContractError::BeneficiaryNotRegistered {
+            address: address.into_string(),
+        });
+    }
+    BENEFICIARIES.remove(deps.storage, &address);
+
+    Ok(Response::new()
+        .add_attribute("action", "remove_beneficiary")
+        .add_attribute("address", address))
+}
+
+fn validate_community_pool_mode(mode: &CommunityPoolMode) -> Result<(), ContractError> {
+    if *mode == CommunityPoolMode::Distribution && cfg!(not(feature = "distribution")) {
+        return Err(ContractError::DistributionFeatureDisabled);
+    }
+    Ok(())
+}
+
+/// Splits the community share of a lotto among the beneficiaries its participants voted for,
+/// proportionally to their weight. The rounding dust goes to the heaviest beneficiary.
+fn community_share_msgs(
+    deps: Deps,
+    config: &Config,
+    env: &Env,
+    lotto: &Lotto,
+    amount: Coin,
+) -> Result<Vec<CosmosMsg>, ContractError> {
+    // Nothing to send without a community share, or one rounded down to zero
+    if amount.amount.is_zero() {
+        return Ok(vec![]);
+    }
+    let tallies = match &lotto.vote_weighting {
+        Some(_) => vote_tallies(deps.storage, lotto.nonce)?,
+        None => vec![],
+    };
+    if tallies.is_empty() {
+        // A beneficiary removed since the lotto was created is no longer paid
+        let beneficiary = lotto
+            .beneficiary
+            .as_ref()
+            .filter(|beneficiary| BENEFICIARIES.has(deps.storage, beneficiary));
+        return Ok(vec![community_pool_msg(config, env, beneficiary, amount)?]);
+    }
+
+    let total_weight: Uint128 = tallies
+        .iter()
+        .map(|(_, tally)| tally.weight.atomics())
+        .sum();
+    let mut shares: Vec<Uint128> = tallies
+        .iter()
+        .map(|(_, tally)| {
+            amount
+                .amount
+                .multiply_ratio(tally.weight.atomics(), total_weight)
+        })
+        .collect();
+    let dust = amount.amount - shares.iter().sum::<Uint128>();
+    let heaviest = (0..tallies.len())
+        .rev()
+        .max_by_key(|&i| tallies[i].1.weight)
+        .unwrap_or_default();
+    shares[heaviest] += dust;
+
+    // The shares of beneficiaries removed since the votes were cast go to the community pool
+    let mut msgs = vec![];
+    let mut unpaid = Uint128::zero();
+    for ((beneficiary, _), share) in tallies.into_iter().zip(shares) {
+        if share.is_zero() {
+            continue;
+        }
+        if !BENEFICIARIES.has(deps.storage, &beneficiary) {
+            unpaid += share;
+            continue;
+        }
+        msgs.push(
+            BankMsg::Send {
+                to_address: beneficiary.into_string(),
+                amount: vec![Coin::new(share.u128(), amount.denom.clone())],
+            }
+            .into(),
+        );
+    }
+    if !unpaid.is_zero() {
+        msgs.push(community_pool_msg(
+            config,
+            env,
+            None,
+            Coin::new(unpaid.u128(), amount.denom),
+        )?);
+    }
+    Ok(msgs)
+}
+
+/// The votes of a lotto per beneficiary, in ascending beneficiary order.
+/// There is at most one tally per approved beneficiary.
+fn vote_tallies(storage: &dyn Storage, lotto_id: u64) -> StdResult<Vec<(Addr, Tally)>> {
+    VOTE_TALLIES
+        .prefix(lotto_id)
+        .range(storage, None, None, Order::Ascending)
+        .collect()
+}
+
+/// Sends the community share of a lotto to its beneficiary if it has one,
+/// otherwise to the community pool set up in the config
+#[cfg_attr(not(feature = "distribution"), allow(unused_variables))]
+fn community_pool_msg(
+    config: &Config,
+    env: &Env,
+    beneficiary: Option<&Addr>,
+    amount: Coin,
+) -> Result<CosmosMsg, ContractError> {
+    if let Some(beneficiary) = beneficiary {
+        return Ok(BankMsg::Send {
+            to_address: beneficiary.to_string(),
+            amount: vec![amount],
+        }
+        .into());
+    }
+    match config.community_pool_mode {
+        CommunityPoolMode::Address => Ok(BankMsg::Send {
+            to_address: config.community_pool.to_string(),
+            amount: vec![amount],
+        }
+        .into()),
+        #[cfg(feature = "distribution")]
+        CommunityPoolMode::Distribution => Ok(CosmosMsg::Stargate {
+            type_url: "/cosmos.distribution.v1beta1.MsgFundCommunityPool".to_string(),
+            value: encode_msg_fund_community_pool(&amount, &env.contract.address).into(),
+        }),
+        #[cfg(not(feature = "distribution"))]
+        CommunityPoolMode::Distribution => Err(ContractError::DistributionFeatureDisabled),
+    }
+}
+
+// For chains that have a community pool module.
+// Neutron has a community pool built as a cosmwasm contract, use the Address mode there.
+#[cfg(feature = "distribution")]
+fn encode_msg_fund_community_pool(amount: &Coin, depositor: &Addr) -> Vec<u8> {
+    // Coin: https://github.com/cosmos/cosmos-sdk/blob/v0.45.15/proto/cosmos/base/v1beta1/coin.proto#L14-L19
+    // MsgFundCommunityPool: https://github.com/cosmos/cosmos-sdk/blob/v0.45.15/proto/cosmos/distribution/v1beta1/tx.proto#L69-L76
+    let coin = anybuf::Anybuf::new()
+        .append_string(1, &amount.denom)
+        .append_string(2, amount.amount.to_string());
+    anybuf::Anybuf::new()
+        .append_message(1, &coin)
+        .append_string(2, depositor)
+        .into_vec()
+}
+
+#[entry_point]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<QueryResponse> {
+    let response = match msg {
+        QueryMsg::Lotto { lotto_nonce } => to_binary(&query_lotto(deps, env, lotto_nonce)?)?,
+        QueryMsg::VerifyDraw { lotto_id } => to_binary(&query_verify_draw(deps, lotto_id)?)?,
+        QueryMsg::TicketsByAddress {
+            address,
+            start_after,
+            limit,
+        } => to_binary(&query_tickets_by_address(
+            deps,
+            address,
+            start_after,
+            limit,
+        )?)?,
+        QueryMsg::WinningsByAddress {
+            address,
+            start_after,
+            limit,
+        } => to_binary(&query_winnings_by_address(
+            deps,
+            address,
+            start_after,
+            limit,
+        )?)?,
+        QueryMsg::SimulatePayout { lotto_id } => {
+            to_binary(&query_simulate_payout(deps, lotto_id)?)?
+        }
+        QueryMsg::Odds { lotto_id, address } => to_binary(&query_odds(deps, lotto_id, address)?)?,
+        QueryMsg::Stats {} => to_binary(&STATS.may_load(deps.storage)?.unwrap_or_default())?,
+        QueryMsg::CreatorStats { creator } => {
+            let creator = deps.api.addr_validate(&creator)?;
+            to_binary(
+                &CREATOR_STATS
+                    .may_load(deps.storage, &creator)?
+                    .unwrap_or_default(),
+            )?
+        }
+        QueryMsg::ProtocolBalances {} => to_binary(&query_protocol_balances(deps)?)?,
+        QueryMsg::NoisFeeReserve {} => to_binary(&query_nois_fee_reserve(deps)?)?,
+        QueryMsg::Solvency {} => to_binary(&query_solvency(deps, env)?)?,
+        QueryMsg::RevenueRecipients {} => to_binary(&query_revenue_recipients(deps)?)?,
+        QueryMsg::Hooks {} => to_binary(&query_hooks(deps)?)?,
+        QueryMsg::PayoutChannel { address, lotto_id } => {
+            to_binary(&query_payout_channel(deps, address, lotto_id)?)?
+        }
+        QueryMsg::Claimable { address } => to_binary(&query_claimable(deps, address)?)?,
+        QueryMsg::Nonce { address } => to_binary(&query_nonce(deps, address)?)?,
+        QueryMsg::Subscriptions { start_after, limit } => {
+            to_binary(&query_subscriptions(deps, start_after, limit)?)?
+        }
+        QueryMsg::PlayerBalance { address } => to_binary(&query_player_balance(deps, address)?)?,
+        QueryMsg::Withdrawals { start_after, limit } => {
+            to_binary(&query_withdrawals(deps, start_after, limit)?)?
+        }
+        QueryMsg::Votes { lotto_id } => to_binary(&query_votes(deps, lotto_id)?)?,
+        QueryMsg::Beneficiaries { start_after, limit } => {
+            to_binary(&query_beneficiaries(deps, start_after, limit)?)?
+        }
+        QueryMsg::LottosDesc {
+            creator,
+            denom,
+            status,
+            is_active,
+            start_after,
+            limit,
+        } => to_binary(&query_lottos(
+            deps,
+            env,
+            creator,
+            denom,
+            status,
+            is_active,
+            start_after,
+            limit,
+            Order::Descending,
+        )?)?,
+        QueryMsg::LottosAsc {
+            creator,
+            denom,
+            status,
+            is_active,
+            start_after,
+            limit,
+        } => to_binary(&query_lottos(
+            deps,
+            env,
+            creator,
+            denom,
+            status,
+            is_active,
+            start_after,
+            limit,
+            Order::Ascending,
+        )?)?,
+        QueryMsg::Config {} => to_binary(&query_config(deps)?)?,
+    };
+    Ok(response)
+}
+
+fn query_lotto(deps: Deps, env: Env, nonce: u64) -> StdResult<LottoResponse> {
+    let lotto = LOTTOS.load(deps.storage, nonce)?;
+    Ok(lotto_response(&env, lotto))
+}
+
+fn lotto_response(env: &Env, lotto: Lotto) -> LottoResponse {
+    let winners = lotto
+        .winners
+        .map(|winners| winners.iter().map(|wn| wn.clone().into_string()).collect());
+    let is_expired = env.block.time > lotto.expiration;
+    LottoResponse {
+        nonce: lotto.nonce,
+        ticket_price: lotto.ticket_price,
+        balance: lotto.balance,
+        participants: lotto
+            .participants
+            .iter()
+            .map(|dep| dep.to_string())
+            .collect(),
+        winners,
+        is_expired,
+        expiration: lotto.expiration,
+        creator: lotto.creator.to_string(),
+        number_of_winners: lotto.number_of_winners,
+        community_pool_percentage: lotto.community_pool_percentage,
+        status: lotto.status,
+        commitment: lotto
+            .commit_reveal
+            .as_ref()
+            .map(|commit_reveal| commit_reveal.commitment.clone()),
+        reveal_deadline: lotto
+            .commit_reveal
+            .map(|commit_reveal| commit_reveal.reveal_deadline),
+        beneficiary: lotto.beneficiary.map(Addr::into_string),
+        vote_weighting: lotto.vote_weighting,
+        series: lotto.series,
+        randomness_source: lotto.randomness_source,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn query_lottos(
+    deps: Deps,
+    env: Env,
+    creator: Option<String>,
+    denom: Option<String>,
+    status: Option<LottoStatus>,
+    is_active: Option<bool>,
+    start_after: Option<u64>,
+    limit: Option<u64>,
+    order: Order,
+) -> StdResult<LottosResponse> {
+    let limit = limit.unwrap_or(MAX_LIMIT as u64).min(MAX_LIMIT as u64) as usize;
+    let (low_bound, top_bound) = match order {
+        Order::Ascending => (start_after.map(Bound::exclusive), None),
+        Order::Descending => (None, start_after.map(Bound::exclusive)),
+    };
+    let creator = creator
+        .map(|creator| deps.api.addr_validate(&creator))
+        .transpose()?;
+    // Active lottos are always open, so only open lottos need to be scanned for them
+    let status = match (status, is_active) {
+        (None, Some(true)) => Some(LottoStatus::Open),
+        (status, _) => status,
+    };
+
+    // Scan the most selective index, the other filters are applied to its entries
+    let candidates = match (&creator, &denom, &status) {
+        (Some(creator), _, _) => LOTTOS.idx.creator.prefix(creator.clone()).range(
+            deps.storage,
+            low_bound,
+            top_bound,
+            order,
+        ),
+        (None, Some(denom), _) => {
+            LOTTOS
+                .idx
+                .denom
+                .prefix(denom.clone())
+                .range(deps.storage, low_bound, top_bound, order)
+        }
+        (None, None, Some(status)) => LOTTOS.idx.status.prefix(status.index_key()).range(
+            deps.storage,
+            low_bound,
+            top_bound,
+            order,
+        ),
+        (None, None, None) => LOTTOS.range(deps.storage, low_bound, top_bound, order),
+    };
+    // The scan is bounded, lottos that don't match the other filters count against it too
+    let mut lottos = vec![];
+    let mut scanned = 0;
+    let mut last_scanned = None;
+    for item in candidates.take(MAX_SCANNED_LOTTOS) {
+        let (nonce, lotto) = item?;
+        scanned += 1;
+        last_scanned = Some(nonce);
+        let matches = creator.iter().all(|creator| lotto.creator == *creator)
+            && denom.iter().all(|denom| lotto.ticket_price.denom == *denom)
+            && status.iter().all(|status| lotto.status == *status)
+            && is_active
+                .iter()
+                .all(|is_active| (lotto.expiration > env.block.time) == *is_active);
+        if matches {
+            lottos.push(lotto_response(&env, lotto));
+            if lottos.len() == limit {
+                break;
+            }
+        }
+    }
+    let next_start_after = if lottos.len() == limit || scanned == MAX_SCANNED_LOTTOS {
+        last_scanned
+    } else {
+        None
+    };
+    Ok(LottosResponse {
+        lottos,
+        next_start_after,
+    })
+}
+
+fn query_verify_draw(deps: Deps, lotto_id: u64) -> StdResult<VerifyDrawResponse> {
+    let lotto = LOTTOS.load(deps.storage, lotto_id)?;
+    let (draw, winners) = match (lotto.draw.clone(), lotto.winners.clone()) {
+        (Some(draw), Some(winners)) => (draw, winners),
+        _ => return Err(StdError::generic_err("Lotto has not been drawn yet")),
+    };
+    let randomness: [u8; 32] = draw.randomness.to_array()?;
+    let derives_from_beacon = draw_randomness(&lotto, &draw.beacon.to_array()?) == Some(randomness);
+    let same_participants = participants_hash(&lotto.participants)? == draw.participants_hash;
+    let expected_winners = nois::pick(
+        randomness,
+        winners_count(&lotto),
+        lotto.participants.clone(),
+    );
+    let (secret, entropy) = match lotto.commit_reveal {
+        Some(commit_reveal) => (commit_reveal.secret, Some(commit_reveal.entropy)),
+        None => (None, None),
+    };
+    Ok(VerifyDrawResponse {
+        lotto_id,
+        randomness: draw.randomness,
+        beacon: draw.beacon,
+        published: draw.published,
+        secret,
+        entropy,
+        participants: lotto.participants.iter().map(|p| p.to_string()).collect(),
+        is_valid: derives_from_beacon && same_participants && expected_winners == winners,
+        winners: winners.iter().map(|w| w.to_string()).collect(),
+    })
+}
+
+fn query_tickets_by_address(
+    deps: Deps,
+    address: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<AddressLottosResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    let limit = limit.unwrap_or(MAX_LIMIT).min(MAX_LIMIT) as usize;
+    let lottos = TICKETS
+        .prefix(&address)
+        .range(
+            deps.storage,
+            None,
+            start_after.map(Bound::exclusive),
+            Order::Descending,
+        )
+        .take(limit)
+        .map(|item| {
+            let (lotto_id, record) = item?;
+            let amount_won = WINNINGS
+                .may_load(deps.storage, (&address, lotto_id))?
+                .map(|winnings| winnings.amount_won)
+                .unwrap_or_default();
+            Ok(AddressLotto {
+                lotto_id,
+                tickets: record.tickets,
+                amount_won,
+                denom: record.denom,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(AddressLottosResponse { lottos })
+}
+
+fn query_winnings_by_address(
+    deps: Deps,
+    address: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<AddressLottosResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    let limit = limit.unwrap_or(MAX_LIMIT).min(MAX_LIMIT) as usize;
+    let lottos = WINNINGS
+        .prefix(&address)
+        .range(
+            deps.storage,
+            None,
+            start_after.map(Bound::exclusive),
+            Order::Descending,
+        )
+        .take(limit)
+        .map(|item| {
+            let (lotto_id, record) = item?;
+            let tickets = TICKETS
+                .may_load(deps.storage, (&address, lotto_id))?
+                .map(|tickets| tickets.tickets)
+                .unwrap_or_default();
+            Ok(AddressLotto {
+                lotto_id,
+                tickets,
+                amount_won: record.amount_won,
+                denom: record.denom,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(AddressLottosResponse { lottos })
+}
+
+fn query_simulate_payout(deps: Deps, lotto_id: u64) -> StdResult<PayoutResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let lotto = LOTTOS.load(deps.storage, lotto_id)?;
+    let Payout {
+        amount_community_pool,
+        amount_creator,
+        amount_protocol,
+        amount_winner,
+    } = payout(&config, &lotto);
+    let winners = winners_count(&lotto) as u32;
+    let denom = lotto.ticket_price.denom;
+    Ok(PayoutResponse {
+        lotto_id,
+        balance: Coin::new(lotto.balance.u128(), denom.clone()),
+        community_pool: Coin::new(amount_community_pool.u128(), denom.clone()),
+        creator: Coin::new(amount_creator.u128(), denom.clone()),
+        protocol: Coin::new(amount_protocol.u128(), denom.clone()),
+        per_winner: Coin::new(amount_winner.u128(), denom),
+        winners,
+    })
+}
+
+fn query_odds(deps: Deps, lotto_id: u64, address: String) -> StdResult<OddsResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    let lotto = LOTTOS.load(deps.storage, lotto_id)?;
+    let tickets = lotto
+        .participants
+        .iter()
+        .filter(|participant| **participant == address)
+        .count() as u32;
+    let total_tickets = lotto.participants.len() as u32;
+    Ok(OddsResponse {
+        lotto_id,
+        address: address.into_string(),
+        tickets,
+        total_tickets,
+        probability: win_probability(total_tickets, tickets, lotto.number_of_winners),
+    })
+}
+
+/// Probability that at least one of `tickets` out of `total` is among the `winners` tickets
+/// drawn without replacement, which is how `nois::pick` samples.
+///
+/// The chance to lose is C(total - tickets, winners) / C(total, winners). Since that equals
+/// C(total - winners, tickets) / C(total, tickets), it is expanded into whichever product
+/// has fewer factors and kept as an exact fraction until the final rounding.
+fn win_probability(total: u32, tickets: u32, winners: u32) -> Decimal {
+    let winners = winners.min(total);
+    if tickets == 0 || winners == 0 {
+        return Decimal::zero();
+    }
+    if tickets + winners > total {
+        // Not enough losing tickets to fill every winning slot
+        return Decimal::one();
+    }
+    let (factors, others) = (tickets.min(winners), tickets.max(winners));
+    // Keeps the fraction small enough for the factors below to never overflow
+    let limit = Uint256::one() << 192;
+    let mut lose_numerator = Uint256::one();
+    let mut lose_denominator = Uint256::one();
+    for i in 0..factors {
+        lose_numerator *= Uint256::from(total - others - i);
+        lose_denominator *= Uint256::from(total - i);
+        let divisor = gcd(lose_numerator, lose_denominator);
+        lose_numerator /= divisor;
+        lose_denominator /= divisor;
+        while lose_denominator > limit {
+            lose_numerator >>= 64;
+            lose_denominator >>= 64;
+        }
+    }
+    let atomics =
+        (lose_denominator - lose_numerator) * Uint256::from(10u128.pow(18)) / lose_denominator;
+    // A probability never exceeds 10^18 atomics
+    Decimal::new(Uint128::try_from(atomics).unwrap())
+}
+
+fn gcd(mut a: Uint256, mut b: Uint256) -> Uint256 {
+    while !b.is_zero() {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+fn query_protocol_balances(deps: Deps) -> StdResult<ProtocolBalancesResponse> {
+    let balances = PROTOCOL_BALANCES
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|balance| Coin {
+            denom: balance.as_ref().unwrap().clone().0,
+            amount: balance.unwrap().1,
+        })
+        .collect();
+    Ok(ProtocolBalancesResponse { balances })
+}
+
+fn query_nois_fee_reserve(deps: Deps) -> StdResult<NoisFeeReserveResponse> {
+    let balances = NOIS_FEE_RESERVE
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|reserve| reserve.map(|(denom, amount)| Coin { denom, amount }))
+        .collect::<StdResult<_>>()?;
+    Ok(NoisFeeReserveResponse { balances })
+}
+
+fn query_votes(deps: Deps, lotto_id: u64) -> StdResult<VotesResponse> {
+    let lotto = LOTTOS.load(deps.storage, lotto_id)?;
+    let tallies = match &lotto.vote_weighting {
+        Some(_) => vote_tallies(deps.storage, lotto_id)?,
+        None => vec![],
+    };
+    Ok(VotesResponse {
+        lotto_id,
+        vote_weighting: lotto.vote_weighting,
+        tallies: tallies
+            .into_iter()
+            .map(|(beneficiary, tally)| VoteTally {
+                beneficiary: beneficiary.into_string(),
+                tickets: tally.tickets,
+                voters: tally.voters,
+                weight: tally.weight,
+            })
+            .collect(),
+    })
+}
+
+fn query_beneficiaries(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<BeneficiariesResponse> {
+    let start_after = start_after
+        .map(|address| deps.api.addr_validate(&address))
+        .transpose()?;
+    let limit = limit.unwrap_or(MAX_LIMIT).min(MAX_LIMIT) as usize;
+    let beneficiaries = BENEFICIARIES
+        .range(
+            deps.storage,
+            start_after.as_ref().map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| {
+            let (address, beneficiary) = item?;
+            Ok(BeneficiaryResponse {
+                address: address.into_string(),
+                name: beneficiary.name,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(BeneficiariesResponse { beneficiaries })
+}
+
+fn query_hooks(deps: Deps) -> StdResult<HooksResponse> {
+    let hooks = HOOKS
+        .keys(deps.storage, None, None, Order::Ascending)
+        .map(|contract| contract.map(Addr::into_string))
+        .collect::<StdResult<_>>()?;
+    Ok(HooksResponse { hooks })
+}
+
+fn query_payout_channel(
+    deps: Deps,
+    address: String,
+    lotto_id: Option<u64>,
+) -> StdResult<PayoutChannelResponse> {
+    let owner = deps.api.addr_validate(&address)?;
+    let payout_channel = match lotto_id {
+        Some(lotto_id) => LOTTO_PAYOUT_CHANNELS.may_load(deps.storage, (&owner, lotto_id))?,
+        None => PAYOUT_CHANNELS.may_load(deps.storage, &owner)?,
+    };
+    Ok(PayoutChannelResponse {
+        address,
+        lotto_id,
+        payout_channel,
+    })
+}
+
+fn query_player_balance(deps: Deps, address: String) -> StdResult<PlayerBalanceResponse> {
+    let player = deps.api.addr_validate(&address)?;
+    let coins = PLAYER_BALANCES
+        .prefix(&player)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, amount)| Coin::new(amount.u128(), denom)))
+        .collect::<StdResult<_>>()?;
+    let credit_winnings = CREDIT_WINNINGS.has(deps.storage, &player);
+    Ok(PlayerBalanceResponse {
+        address,
+        coins,
+        credit_winnings,
+    })
+}
+
+fn query_subscriptions(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<SubscriptionsResponse> {
+    let start_after = start_after
+        .map(|address| deps.api.addr_validate(&address))
+        .transpose()?;
+    let limit = limit.unwrap_or(MAX_LIMIT).min(MAX_LIMIT) as usize;
+    let subscriptions = SUBSCRIPTIONS
+        .range(
+            deps.storage,
+            start_after.as_ref().map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| {
+            let (subscriber, subscription) = item?;
+            let criteria = subscription.criteria;
+            Ok(SubscriptionResponse {
+                subscriber: subscriber.into_string(),
+                criteria: SubscriptionCriteriaMsg {
+                    creator: criteria.creator.map(Addr::into_string),
+                    denom: criteria.denom,
+                    max_price: criteria.max_price,
+                    series: criteria.series,
+                },
+                tickets_per_lotto: subscription.tickets_per_lotto,
+                max_spend: subscription.max_spend,
+                spent: subscription.spent,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(SubscriptionsResponse { subscriptions })
+}
+
+fn query_nonce(deps: Deps, address: String) -> StdResult<NonceResponse> {
+    let buyer = deps.api.addr_validate(&address)?;
+    let nonce = NONCES.may_load(deps.storage, &buyer)?.unwrap_or_default();
+    Ok(NonceResponse { address, nonce })
+}
+
+fn query_claimable(deps: Deps, address: String) -> StdResult<ClaimableResponse> {
+    let owner = deps.api.addr_validate(&address)?;
+    let coins = CLAIMABLE
+        .prefix(&owner)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, amount)| Coin::new(amount.u128(), denom)))
+        .collect::<StdResult<_>>()?;
+    Ok(ClaimableResponse { address, coins })
+}
+
+fn query_revenue_recipients(deps: Deps) -> StdResult<RevenueRecipientsResponse> {
+    let recipients = REVENUE_RECIPIENTS
+        .may_load(deps.storage)?
+        .unwrap_or_default()
+        .into_iter()
+        .map(|recipient| RevenueRecipientMsg {
+            destination: match recipient.destination {
+                RevenueDestination::Address(address) => RevenueDestinationMsg::Address {
+                    address: address.into_string(),
+                },
+                RevenueDestination::NoisFeeReserve => RevenueDestinationMsg::NoisFeeReserve {},
+            },
+            weight: recipient.weight,
+        })
+        .collect();
+    Ok(RevenueRecipientsResponse { recipients })
+}
+
+fn query_withdrawals(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<WithdrawalsResponse> {
+    let limit = limit.unwrap_or(MAX_LIMIT).min(MAX_LIMIT) as usize;
+    let withdrawals = WITHDRAWALS
+        .range(
+            deps.storage,
+            None,
+            start_after.map(Bound::exclusive),
+            Order::Descending,
+        )
+        .take(limit)
+        .map(|item| {
+            let (id, withdrawal) = item?;
+            Ok(WithdrawalResponse {
+                id,
+                initiator: withdrawal.initiator.into_string(),
+                recipient: withdrawal.recipient.into_string(),
+                coins: withdrawal.coins,
+                time: withdrawal.time,
+                height: withdrawal.height,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(WithdrawalsResponse { withdrawals })
+}
+
+fn query_solvency(deps: Deps, env: Env) -> StdResult<SolvencyResponse> {
+    let mut ledger: BTreeMap<String, (Uint128, Uint128, Uint128)> = BTreeMap::new();
+    for coin in deps.querier.query_all_balances(&env.contract.address)? {
+        ledger.entry(coin.denom).or_default().0 = coin.amount;
+    }
+    for item in ESCROW.range(deps.storage, None, None, Order::Ascending) {
+        let (denom, escrowed) = item?;
+        ledger.entry(denom).or_default().1 = escrowed;
+    }
+    for item in UNTRACKED_PAYOUTS.range(deps.storage, None, None, Order::Ascending) {
+        let (denom, untracked) = item?;
+        ledger.entry(denom).or_default().2 = untracked;
+    }
+    let denoms: Vec<DenomSolvency> = ledger
+        .into_iter()
+        .map(
+            |(denom, (balance, escrowed, untracked_payouts))| DenomSolvency {
+                denom,
+                balance,
+                escrowed,
+                surplus: balance.saturating_sub(escrowed),
+                shortfall: escrowed.saturating_sub(balance),
+                untracked_payouts,
+            },
+        )
+        .collect();
+    Ok(SolvencyResponse {
+        is_solvent: denoms.iter().all(|denom| denom.shortfall.is_zero()),
+        denoms,
+    })
+}
+
+fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    Ok(ConfigResponse {
+        version: CONFIG_RESPONSE_VERSION,
+        manager: config.manager.to_string(),
+        nois_proxy: config.nois_proxy.to_string(),
+        lotto_nonce: config.lotto_nonce,
+        community_pool: config.community_pool.to_string(),
+        protocol_commission_percent: config.protocol_commission_percent,
+        creator_commission_percent: config.creator_commission_percent,
+        is_paused: config.is_paused,
+        randomness_source: config.randomness_source,
+        max_duration_seconds: config.max_duration_seconds,
+        allowed_denoms: config.allowed_denoms,
+        community_pool_mode: config.community_pool_mode,
+        swap_router: config.swap_router.map(Addr::into_string),
+        reserve_pays_nois_fee: config.reserve_pays_nois_fee,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::{
+        mock_dependencies, mock_env, mock_info, MockApi, MockQuerier, MockStorage,
+        MOCK_CONTRACT_ADDR,
+    };
+    use cosmwasm_std::{
+        from_binary, Addr, ContractResult, Empty, HexBinary, IbcMsg, IbcTimeout, OwnedDeps, SubMsg,
+        SubMsgResponse, SystemError, SystemResult, Timestamp, WasmMsg, WasmQuery,
+    };
+    use cw_multi_test::{App, AppResponse, ContractWrapper, Executor};
+    use k256::ecdsa::signature::Signer;
+    use k256::ecdsa::{Signature, SigningKey};
+    use nois::{NoisCallback, ProxyExecuteMsg};
+
+    use crate::ibc::IBC_TRANSFER_TIMEOUT_SECONDS;
+    use crate::msg::{HookExecuteMsg, NoisPriceResponse, NoisProxyQueryMsg};
+
+    const CREATOR: &str = "creator1";
+    const PROXY_ADDRESS: &str = "the proxy of choice";
+    const MANAGER: &str = "manager";
+    const COM_POOL: &str = "community_pool";
+    // Price of a randomness request in unois, the only denom the mocked proxy accepts
+    const NOIS_PRICE: u128 = 50_000_000;
+
+    fn instantiate_contract() -> OwnedDeps<MockStorage, MockApi, MockQuerier, Empty> {
+        let mut deps = mock_dependencies();
+        deps.querier.update_wasm(|query| match query {
+            WasmQuery::Smart { contract_addr, msg } if contract_addr == PROXY_ADDRESS => {
+                let NoisProxyQueryMsg::Price { denom } = from_binary(msg).unwrap();
+                let price = if denom == "unois" {
+                    Some(Uint128::new(NOIS_PRICE))
+                } else {
+                    None
+                };
+                SystemResult::Ok(ContractResult::Ok(
+                    to_binary(&NoisPriceResponse { price }).unwrap(),
+                ))
+            }
+            _ => SystemResult::Err(SystemError::UnsupportedRequest {
+                kind: "wasm".to_string(),
+            }),
+        });
+        let msg = InstantiateMsg {
+            manager: MANAGER.to_string(),
+            nois_proxy: PROXY_ADDRESS.to_string(),
+            community_pool: COM_POOL.to_string(),
+            protocol_commission_percent: 5,
+            creator_commission_percent: 15,
+            randomness_source: None,
+            max_duration_seconds: None,
+            allowed_denoms: None,
+            community_pool_mode: None,
+            swap_router: None,
+        };
+
+        let info = mock_info(CREATOR, &[]);
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        deps
+    }
+
+    // Lotto 0 with two winners and one ticket for each of participant-1 to participant-5
+    fn lotto_with_five_participants() -> OwnedDeps<MockStorage, MockApi, MockQuerier, Empty> {
+        let mut deps = instantiate_contract();
+        let msg = ExecuteMsg::CreateLotto {
+            ticket_price: Coin::new(100_000_000, "untrn"),
+            duration_seconds: 90,
+            number_of_winners: 2,
+            community_pool_percentage: 20,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
+        };
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        for i in 1..=5 {
+            let msg = ExecuteMsg::BuyTicket {
+                lotto_id: 0,
+                entropy: None,
+                vote: None,
+            };
+            let info = mock_info(
+                &format!("participant-{i}"),
+                &[Coin::new(100_000_000, "untrn")],
+            );
+            execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        }
+        deps
+    }
+
+    // Settles lotto 0 with a beacon that draws participant-4 and participant-5
+    fn settle_first_lotto(mut deps: DepsMut) {
+        let msg = ExecuteMsg::NoisReceive {
+            callback: NoisCallback {
+                job_id: "lotto-0".to_string(),
+                published: Timestamp::from_seconds(1682086395),
+                randomness: HexBinary::from_hex(
+                    "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa115",
+                )
+                .unwrap(),
+            },
+        };
+        let info = mock_info(PROXY_ADDRESS, &[]);
+        execute(deps.branch(), mock_env(), info, msg).unwrap();
+        let msg = ExecuteMsg::Settle {
+            lotto_id: 0,
+            limit: None,
+        };
+        execute(deps, mock_env(), mock_info("anyone", &[]), msg).unwrap();
+    }
+
+    #[test]
+    fn proper_instantiation() {
+        let deps = instantiate_contract();
+        let env = mock_env();
+
+        // it worked, let's query the state
+        let res = query(deps.as_ref(), env, QueryMsg::Config {}).unwrap();
+        let config: ConfigResponse = from_binary(&res).unwrap();
+        assert_eq!(
+            config,
+            ConfigResponse {
+                version: CONFIG_RESPONSE_VERSION,
+                manager: MANAGER.to_string(),
+                nois_proxy: PROXY_ADDRESS.to_string(),
+                lotto_nonce: 0,
+                community_pool: COM_POOL.to_string(),
+                protocol_commission_percent: 5,
+                creator_commission_percent: 15,
+                is_paused: false,
+                randomness_source: RandomnessSource::Nois,
+                max_duration_seconds: DEFAULT_MAX_DURATION_SECONDS,
+                allowed_denoms: vec![],
+                community_pool_mode: CommunityPoolMode::Address,
+                swap_router: None,
+                reserve_pays_nois_fee: false,
+            }
+        );
+    }
+
+    #[test]
+    fn create_lotto_respects_limits() {
+        let mut deps = instantiate_contract();
+        let env = mock_env();
+        let msg = ExecuteMsg::SetConfig {
+            nois_proxy: None,
+            manager: None,
+            lotto_nonce: None,
+            community_pool: None,
+            protocol_commission_percent: None,
+            creator_commission_percent: None,
+            is_paused: None,
+            randomness_source: None,
+            max_duration_seconds: Some(3600),
+            allowed_denoms: Some(vec!["untrn".to_string()]),
+            community_pool_mode: None,
+            swap_router: None,
+            reserve_pays_nois_fee: None,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(MANAGER, &[]), msg).unwrap();
+        let config: ConfigResponse =
+            from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::Config {}).unwrap()).unwrap();
+        assert_eq!(config.max_duration_seconds, 3600);
+        assert_eq!(config.allowed_denoms, vec!["untrn".to_string()]);
+
+        let create = |duration_seconds: u64, denom: &str| ExecuteMsg::CreateLotto {
+            ticket_price: Coin::new(100, denom),
+            duration_seconds,
+            number_of_winners: 1,
+            community_pool_percentage: 20,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
+        };
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            info.clone(),
+            create(3601, "untrn"),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::DurationTooLong {
+                max_duration_seconds: 3600
+            }
+        );
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            info.clone(),
+            create(3600, "uatom"),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::DenomNotAllowed {
+                denom: "uatom".to_string()
+            }
+        );
+        execute(deps.as_mut(), env.clone(), info, create(3600, "untrn")).unwrap();
+        let config: ConfigResponse =
+            from_binary(&query(deps.as_ref(), env, QueryMsg::Config {}).unwrap()).unwrap();
+        assert_eq!(config.lotto_nonce, 1);
+    }
+
+    #[test]
+    fn create_lotto_pays_exact_nois_fee() {
+        let mut deps = instantiate_contract();
+        let env = mock_env();
+        let msg = ExecuteMsg::CreateLotto {
+            ticket_price: Coin::new(100_000_000, "untrn"),
+            duration_seconds: 90,
+            number_of_winners: 2,
+            community_pool_percentage: 20,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
+        };
+
+        // creator pays in a denom the proxy does not accept
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "untrn")]);
+        let err = execute(deps.as_mut(), env.clone(), info, msg.clone()).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::UnsupportedNoisFeeDenom {
+                denom: "untrn".to_string()
+            }
+        );
+
+        // creator pays less than the proxy price
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE - 1, "unois")]);
+        let err = execute(deps.as_mut(), env.clone(), info, msg.clone()).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InsufficientNoisFee {
+                required: Coin::new(NOIS_PRICE, "unois")
+            }
+        );
+
+        // creator overpays and gets the excess back
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE + 7, "unois")]);
+        let res = execute(deps.as_mut(), env.clone(), info, msg.clone()).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(WasmMsg::Execute {
+                    contract_addr: PROXY_ADDRESS.to_string(),
+                    msg: to_binary(&ProxyExecuteMsg::GetRandomnessAfter {
+                        after: env.block.time.plus_seconds(90),
+                        job_id: "lotto-0".to_string(),
+                    })
+                    .unwrap(),
+                    funds: vec![Coin::new(NOIS_PRICE, "unois")],
+                }),
+                SubMsg::new(BankMsg::Send {
+                    to_address: CREATOR.to_string(),
+                    amount: vec![Coin::new(7, "unois")],
+                }),
+            ]
+        );
+
+        // without funds and an empty reserve the creator has to pay
+        let info = mock_info(CREATOR, &[]);
+        let err = execute(deps.as_mut(), env, info, msg).unwrap_err();
+        assert_eq!(err, ContractError::NoFundsProvided);
+    }
+
+    #[test]
+    fn create_lotto_with_nois_fee_reserve() {
+        let mut deps = instantiate_contract();
+        let env = mock_env();
+        PROTOCOL_BALANCES
+            .save(
+                deps.as_mut().storage,
+                "unois".to_string(),
+                &Uint128::new(NOIS_PRICE + 10),
+            )
+            .unwrap();
+        ESCROW
+            .save(
+                deps.as_mut().storage,
+                "unois".to_string(),
+                &Uint128::new(NOIS_PRICE + 10),
+            )
+            .unwrap();
+
+        // only the manager can fund the reserve
+        let msg = ExecuteMsg::FundNoisFeeReserve {
+            amount: Coin::new(NOIS_PRICE, "unois"),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info(CREATOR, &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized);
+
+        // the reserve cannot be funded with more than the protocol owns
+        let msg = ExecuteMsg::FundNoisFeeReserve {
+            amount: Coin::new(NOIS_PRICE + 11, "unois"),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info(MANAGER, &[]), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InsufficientProtocolBalance {
+                denom: "unois".to_string()
+            }
+        );
+
+        let msg = ExecuteMsg::FundNoisFeeReserve {
+            amount: Coin::new(NOIS_PRICE, "unois"),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(MANAGER, &[]), msg).unwrap();
+        let ProtocolBalancesResponse { balances } =
+            from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::ProtocolBalances {}).unwrap())
+                .unwrap();
+        assert_eq!(balances, vec![Coin::new(10, "unois")]);
+
+        // the reserve only pays once the manager lets it
+        let msg = ExecuteMsg::CreateLotto {
+            ticket_price: Coin::new(100_000_000, "untrn"),
+            duration_seconds: 90,
+            number_of_winners: 2,
+            community_pool_percentage: 20,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
+        };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(CREATOR, &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::NoFundsProvided);
+        let set_config = ExecuteMsg::SetConfig {
+            nois_proxy: None,
+            manager: None,
+            lotto_nonce: None,
+            community_pool: None,
+            protocol_commission_percent: None,
+            creator_commission_percent: None,
+            is_paused: None,
+            randomness_source: None,
+            max_duration_seconds: None,
+            allowed_denoms: None,
+            community_pool_mode: None,
+            swap_router: None,
+            reserve_pays_nois_fee: Some(true),
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(MANAGER, &[]),
+            set_config,
+        )
+        .unwrap();
+
+        // creating a lotto is now free for the creator
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(CREATOR, &[]),
+            msg.clone(),
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(
+            res.attributes.last().unwrap(),
+            Attribute::new("nois_fee", format!("{NOIS_PRICE}unois"))
+        );
+        let NoisFeeReserveResponse { balances } =
+            from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::NoisFeeReserve {}).unwrap())
+                .unwrap();
+        assert!(balances.is_empty());
+
+        // until the reserve runs dry, what is left can't pay another fee
+        let fund = ExecuteMsg::FundNoisFeeReserve {
+            amount: Coin::new(10, "unois"),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(MANAGER, &[]), fund).unwrap();
+        let ProtocolBalancesResponse { balances } =
+            from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::ProtocolBalances {}).unwrap())
+                .unwrap();
+        assert!(balances.is_empty());
+        let err = execute(deps.as_mut(), env, mock_info(CREATOR, &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::NoFundsProvided);
+    }
+
+    #[test]
+    fn lotto_works_with_mock_randomness() {
+        let participants = ["participant-1", "participant-2", "participant-3"];
+        let mut app = App::new(|router, _, storage| {
+            for participant in participants {
+                router
+                    .bank
+                    .init_balance(
+                        storage,
+                        &Addr::unchecked(participant),
+                        vec![Coin::new(100, "untrn")],
+                    )
+                    .unwrap();
+            }
+        });
+        let code_id = app.store_code(Box::new(ContractWrapper::new(execute, instantiate, query)));
+        let lotto_addr = app
+            .instantiate_contract(
+                code_id,
+                Addr::unchecked(MANAGER),
+                &InstantiateMsg {
+                    manager: MANAGER.to_string(),
+                    nois_proxy: PROXY_ADDRESS.to_string(),
+                    community_pool: COM_POOL.to_string(),
+                    protocol_commission_percent: 5,
+                    creator_commission_percent: 15,
+                    randomness_source: Some(RandomnessSource::Mock),
+                    max_duration_seconds: None,
+                    allowed_denoms: None,
+                    community_pool_mode: None,
+                    swap_router: None,
+                },
+                &[],
+                "lotto",
+                None,
+            )
+            .unwrap();
+
+        // creating a lotto is free with the mock source
+        app.execute_contract(
+            Addr::unchecked(CREATOR),
+            lotto_addr.clone(),
+            &ExecuteMsg::CreateLotto {
+                ticket_price: Coin::new(100, "untrn"),
+                duration_seconds: 90,
+                number_of_winners: 1,
+                community_pool_percentage: 20,
+                commitment: None,
+                beneficiary: None,
+                vote_weighting: None,
+                series: None,
+            },
+            &[],
+        )
+        .unwrap();
+        for participant in participants {
+            app.execute_contract(
+                Addr::unchecked(participant),
+                lotto_addr.clone(),
+                &ExecuteMsg::BuyTicket {
+                    lotto_id: 0,
+                    entropy: None,
+                    vote: None,
+                },
+                &[Coin::new(100, "untrn")],
+            )
+            .unwrap();
+        }
+
+        // the randomness cannot be delivered while the lotto is running
+        let receive = ExecuteMsg::ReceiveMockRandomness {
+            job_id: "lotto-0".to_string(),
+        };
+        let err = app
+            .execute_contract(Addr::unchecked("anyone"), lotto_addr.clone(), &receive, &[])
+            .unwrap_err();
+        assert_eq!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::RandomnessTooEarly
+        );
+
+        app.update_block(|block| block.time = block.time.plus_seconds(91));
+        app.execute_contract(Addr::unchecked("anyone"), lotto_addr.clone(), &receive, &[])
+            .unwrap();
+        app.execute_contract(
+            Addr::unchecked("anyone"),
+            lotto_addr.clone(),
+            &ExecuteMsg::Settle {
+                lotto_id: 0,
+                limit: None,
+            },
+            &[],
+        )
+        .unwrap();
+
+        let balance = |app: &App, address: &str| {
+            app.wrap()
+                .query_balance(address, "untrn")
+                .unwrap()
+                .amount
+                .u128()
+        };
+        assert_eq!(balance(&app, CREATOR), 45);
+        assert_eq!(balance(&app, COM_POOL), 60);
+        assert_eq!(balance(&app, lotto_addr.as_str()), 15);
+        let LottoResponse { winners, .. } = app
+            .wrap()
+            .query_wasm_smart(&lotto_addr, &QueryMsg::Lotto { lotto_nonce: 0 })
+            .unwrap();
+        let winners = winners.unwrap();
+        assert_eq!(winners.len(), 1);
+        assert_eq!(balance(&app, &winners[0]), 180);
+
+        // what is left is exactly the protocol commission
+        let solvency: SolvencyResponse = app
+            .wrap()
+            .query_wasm_smart(&lotto_addr, &QueryMsg::Solvency {})
+            .unwrap();
+        assert_eq!(
+            solvency.denoms,
+            vec![DenomSolvency {
+                denom: "untrn".to_string(),
+                balance: Uint128::new(15),
+                escrowed: Uint128::new(15),
+                surplus: Uint128::zero(),
+                shortfall: Uint128::zero(),
+                untracked_payouts: Uint128::zero(),
+            }]
+        );
+    }
+
+    #[test]
+    fn settle_lotto_without_tickets() {
+        let mut app = App::default();
+        let code_id = app.store_code(Box::new(ContractWrapper::new(execute, instantiate, query)));
+        let lotto_addr = app
+            .instantiate_contract(
+                code_id,
+                Addr::unchecked(MANAGER),
+                &InstantiateMsg {
+                    manager: MANAGER.to_string(),
+                    nois_proxy: PROXY_ADDRESS.to_string(),
+                    community_pool: COM_POOL.to_string(),
+                    protocol_commission_percent: 5,
+                    creator_commission_percent: 15,
+                    randomness_source: Some(RandomnessSource::Mock),
+                    max_duration_seconds: None,
+                    allowed_denoms: None,
+                    community_pool_mode: None,
+                    swap_router: None,
+                },
+                &[],
+                "lotto",
+                None,
+            )
+            .unwrap();
+        app.execute_contract(
+            Addr::unchecked(CREATOR),
+            lotto_addr.clone(),
+            &ExecuteMsg::CreateLotto {
+                ticket_price: Coin::new(100, "untrn"),
+                duration_seconds: 90,
+                number_of_winners: 1,
+                community_pool_percentage: 20,
+                commitment: None,
+                beneficiary: None,
+                vote_weighting: None,
+                series: None,
+            },
+            &[],
+        )
+        .unwrap();
+
+        app.update_block(|block| block.time = block.time.plus_seconds(91));
+        app.execute_contract(
+            Addr::unchecked("anyone"),
+            lotto_addr.clone(),
+            &ExecuteMsg::ReceiveMockRandomness {
+                job_id: "lotto-0".to_string(),
+            },
+            &[],
+        )
+        .unwrap();
+        // nobody wins and nothing is paid
+        let res = app
+            .execute_contract(
+                Addr::unchecked("anyone"),
+                lotto_addr.clone(),
+                &ExecuteMsg::Settle {
+                    lotto_id: 0,
+                    limit: None,
+                },
+                &[],
+            )
+            .unwrap();
+        let settled = res
+            .events
+            .iter()
+            .find(|event| event.ty == format!("wasm-{}", events::LOTTO_SETTLED))
+            .unwrap();
+        assert!(!settled.attributes.iter().any(|attr| attr.key == "winners"));
+        let LottoResponse {
+            status, winners, ..
+        } = app
+            .wrap()
+            .query_wasm_smart(&lotto_addr, &QueryMsg::Lotto { lotto_nonce: 0 })
+            .unwrap();
+        assert_eq!(status, LottoStatus::Settled);
+        assert_eq!(winners, Some(vec![]));
+    }
+
+    #[test]
+    fn randomness_source_fixed_at_creation() {
+        let mut deps = instantiate_contract();
+        let env = mock_env();
+        let msg = ExecuteMsg::CreateLotto {
+            ticket_price: Coin::new(100, "untrn"),
+            duration_seconds: 90,
+            number_of_winners: 1,
+            community_pool_percentage: 20,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
+        };
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
+        execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+        // switching the source doesn't affect the lottos already running
+        let msg = ExecuteMsg::SetConfig {
+            nois_proxy: None,
+            manager: None,
+            lotto_nonce: None,
+            community_pool: None,
+            protocol_commission_percent: None,
+            creator_commission_percent: None,
+            is_paused: None,
+            randomness_source: Some(RandomnessSource::Mock),
+            max_duration_seconds: None,
+            allowed_denoms: None,
+            community_pool_mode: None,
+            swap_router: None,
+            reserve_pays_nois_fee: None,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(MANAGER, &[]), msg).unwrap();
+        let mut later = env.clone();
+        later.block.time = env.block.time.plus_seconds(100);
+        let msg = ExecuteMsg::ReceiveMockRandomness {
+            job_id: "lotto-0".to_string(),
+        };
+        let err = execute(deps.as_mut(), later.clone(), mock_info("anyone", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::UnauthorizedReceive);
+        let msg = ExecuteMsg::NoisReceive {
+            callback: NoisCallback {
+                job_id: "lotto-0".to_string(),
+                published: env.block.time.plus_seconds(100),
+                randomness: HexBinary::from([7; 32]),
+            },
+        };
+        execute(deps.as_mut(), later, mock_info(PROXY_ADDRESS, &[]), msg).unwrap();
+        let lotto: LottoResponse =
+            from_binary(&query(deps.as_ref(), env, QueryMsg::Lotto { lotto_nonce: 0 }).unwrap())
+                .unwrap();
+        assert_eq!(lotto.randomness_source, RandomnessSource::Nois);
+        assert_eq!(lotto.status, LottoStatus::Settling);
+    }
+
+    #[test]
+    fn commit_reveal_lotto_works() {
+        let mut deps = instantiate_contract();
+        let mut env = mock_env();
+        let secret = HexBinary::from(b"creator secret");
+        let commitment = HexBinary::from(Sha256::digest(secret.as_slice()).to_vec());
+
+        // the commitment needs to be a sha256 hash
+        let msg = ExecuteMsg::CreateLotto {
+            ticket_price: Coin::new(100, "untrn"),
+            duration_seconds: 90,
+            number_of_winners: 1,
+            community_pool_percentage: 20,
+            commitment: Some(secret.clone()),
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info(CREATOR, &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidCommitment);
+
+        // the beacon is booked for after the reveal deadline
+        let msg = ExecuteMsg::CreateLotto {
+            ticket_price: Coin::new(100, "untrn"),
+            duration_seconds: 90,
+            number_of_winners: 1,
+            community_pool_percentage: 20,
+            commitment: Some(commitment.clone()),
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
+        };
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
+        let res = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(WasmMsg::Execute {
+                contract_addr: PROXY_ADDRESS.to_string(),
+                msg: to_binary(&ProxyExecuteMsg::GetRandomnessAfter {
+                    after: env.block.time.plus_seconds(90 + REVEAL_PERIOD_SECONDS),
+                    job_id: "lotto-0".to_string(),
+                })
+                .unwrap(),
+                funds: vec![Coin::new(NOIS_PRICE, "unois")],
+            })]
+        );
+
+        for (participant, entropy) in [("participant-1", Some(b"dice")), ("participant-2", None)] {
+            let msg = ExecuteMsg::BuyTicket {
+                lotto_id: 0,
+                entropy: entropy.map(HexBinary::from),
+                vote: None,
+            };
+            let info = mock_info(participant, &[Coin::new(100, "untrn")]);
+            execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        }
+        // other lottos don't take entropy
+        let msg = ExecuteMsg::CreateLotto {
+            ticket_price: Coin::new(100, "untrn"),
+            duration_seconds: 90,
+            number_of_winners: 1,
+            community_pool_percentage: 20,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
+        };
+        execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        let msg = ExecuteMsg::BuyTicket {
+            lotto_id: 1,
+            entropy: Some(HexBinary::from(b"dice")),
+            vote: None,
+        };
+        let info = mock_info("participant-1", &[Coin::new(100, "untrn")]);
+        let err = execute(deps.as_mut(), env.clone(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::NotCommitReveal);
+
+        let reveal = ExecuteMsg::Reveal {
+            lotto_id: 0,
+            secret: secret.clone(),
+        };
+        // the secret cannot be revealed before the end of the lotto
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(CREATOR, &[]),
+            reveal.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::LottoNotExpired);
+
+        env.block.time = env.block.time.plus_seconds(91);
+        // only the creator can reveal
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("participant-1", &[]),
+            reveal.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized);
+        // and only the committed secret
+        let msg = ExecuteMsg::Reveal {
+            lotto_id: 0,
+            secret: HexBinary::from(b"another secret"),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info(CREATOR, &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidReveal);
+
+        execute(deps.as_mut(), env.clone(), mock_info(CREATOR, &[]), reveal).unwrap();
+        // the winners are drawn once the beacon published after the reveal deadline arrives
+        let beacon = |published| ExecuteMsg::NoisReceive {
+            callback: NoisCallback {
+                job_id: "lotto-0".to_string(),
+                published,
+                randomness: HexBinary::from([7; 32]),
+            },
+        };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PROXY_ADDRESS, &[]),
+            beacon(env.block.time),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::RandomnessTooEarly);
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PROXY_ADDRESS, &[]),
+            beacon(env.block.time.plus_seconds(REVEAL_PERIOD_SECONDS)),
+        )
+        .unwrap();
+        let msg = ExecuteMsg::Settle {
+            lotto_id: 0,
+            limit: None,
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), msg).unwrap();
+        assert_eq!(res.messages.len(), 3);
+        let lotto: LottoResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::Lotto { lotto_nonce: 0 },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(lotto.status, LottoStatus::Settled);
+        assert_eq!(lotto.winners.unwrap().len(), 1);
+        // the proof shows how the randomness was derived
+        let draw: VerifyDrawResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::VerifyDraw { lotto_id: 0 },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert!(draw.is_valid);
+        assert_eq!(draw.secret, Some(secret));
+        let entropy = draw.entropy.unwrap();
+        let randomness = Sha256::new()
+            .chain_update(b"creator secret")
+            .chain_update(entropy.as_slice())
+            .chain_update([7; 32])
+            .finalize();
+        assert_eq!(draw.randomness.as_slice(), randomness.as_slice());
+
+        // a settled lotto cannot be refunded
+        env.block.time = env.block.time.plus_seconds(REVEAL_PERIOD_SECONDS);
+        let msg = ExecuteMsg::RefundLotto {
+            lotto_id: 0,
+            limit: None,
+        };
+        let err = execute(deps.as_mut(), env, mock_info("anyone", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::LottoClosed);
+    }
+
+    #[test]
+    fn commit_reveal_lotto_refunded_without_reveal() {
+        let mut deps = instantiate_contract();
+        let mut env = mock_env();
+        let secret = HexBinary::from(b"creator secret");
+        let msg = ExecuteMsg::CreateLotto {
+            ticket_price: Coin::new(100, "untrn"),
+            duration_seconds: 90,
+            number_of_winners: 1,
+            community_pool_percentage: 20,
+            commitment: Some(HexBinary::from(Sha256::digest(secret.as_slice()).to_vec())),
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
+        };
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
+        execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        for participant in ["participant-1", "participant-2", "participant-1"] {
+            let msg = ExecuteMsg::BuyTicket {
+                lotto_id: 0,
+                entropy: None,
+                vote: None,
+            };
+            let info = mock_info(participant, &[Coin::new(100, "untrn")]);
+            execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        }
+
+        // the beacon is only accepted after the reveal deadline
+        let beacon = |published| ExecuteMsg::NoisReceive {
+            callback: NoisCallback {
+                job_id: "lotto-0".to_string(),
+                published,
+                randomness: HexBinary::from([7; 32]),
+            },
+        };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PROXY_ADDRESS, &[]),
+            beacon(env.block.time.plus_seconds(100)),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::RandomnessTooEarly);
+
+        // refunds are only possible once the creator had a chance to reveal
+        env.block.time = env.block.time.plus_seconds(90 + REVEAL_PERIOD_SECONDS);
+        let msg = ExecuteMsg::RefundLotto {
+            lotto_id: 0,
+            limit: Some(2),
+        };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::RevealDeadlineNotPassed);
+
+        env.block.time = env.block.time.plus_seconds(1);
+        let reveal = ExecuteMsg::Reveal {
+            lotto_id: 0,
+            secret,
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info(CREATOR, &[]), reveal).unwrap_err();
+        assert_eq!(err, ContractError::RevealDeadlinePassed);
+
+        // the beacon arriving without a revealed secret starts the refund,
+        // which anyone pays out in batches
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PROXY_ADDRESS, &[]),
+            beacon(env.block.time),
+        )
+        .unwrap();
+        assert!(res.messages.is_empty());
+        let status = |deps: Deps| {
+            let lotto: LottoResponse =
+                from_binary(&query(deps, env.clone(), QueryMsg::Lotto { lotto_nonce: 0 }).unwrap())
+                    .unwrap();
+            lotto.status
+        };
+        assert_eq!(status(deps.as_ref()), LottoStatus::Refunding);
+        let msg = ExecuteMsg::Settle {
+            lotto_id: 0,
+            limit: None,
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::LottoClosed);
+
+        let refund = |amount: u128, participant: &str| {
+            SubMsg::new(BankMsg::Send {
+                to_address: participant.to_string(),
+                amount: vec![Coin::new(amount, "untrn")],
+            })
+        };
+        let msg = ExecuteMsg::RefundLotto {
+            lotto_id: 0,
+            limit: Some(2),
+        };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            msg.clone(),
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![refund(100, "participant-1"), refund(100, "participant-2")]
+        );
+        assert_eq!(status(deps.as_ref()), LottoStatus::Refunding);
+        let msg = ExecuteMsg::RefundLotto {
+            lotto_id: 0,
+            limit: None,
+        };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            msg.clone(),
+        )
+        .unwrap();
+        assert_eq!(res.messages, vec![refund(100, "participant-1")]);
+        assert_eq!(status(deps.as_ref()), LottoStatus::Refunded);
+        let err = execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::LottoClosed);
+        // nothing of the lotto stays escrowed
+        assert_eq!(
+            ESCROW
+                .may_load(deps.as_ref().storage, "untrn".to_string())
+                .unwrap()
+                .unwrap_or_default(),
+            Uint128::zero()
+        );
+        // the refunded tickets no longer count as sold
+        let stats: Stats =
+            from_binary(&query(deps.as_ref(), env, QueryMsg::Stats {}).unwrap()).unwrap();
+        assert_eq!(stats.lottos_created, 1);
+        assert_eq!(stats.tickets_sold, 0);
+        assert_eq!(stats.volume, vec![]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "distribution"))]
+    fn distribution_mode_requires_feature() {
+        let mut deps = instantiate_contract();
+        let msg = ExecuteMsg::SetConfig {
+            nois_proxy: None,
+            manager: None,
+            lotto_nonce: None,
+            community_pool: None,
+            protocol_commission_percent: None,
+            creator_commission_percent: None,
+            is_paused: None,
+            randomness_source: None,
+            max_duration_seconds: None,
+            allowed_denoms: None,
+            community_pool_mode: Some(CommunityPoolMode::Distribution),
+            swap_router: None,
+            reserve_pays_nois_fee: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(MANAGER, &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::DistributionFeatureDisabled);
+    }
+
+    /// Splits a protobuf message into its length-delimited fields
+    #[cfg(feature = "distribution")]
+    fn decode_fields(mut bytes: &[u8]) -> Vec<(u64, Vec<u8>)> {
+        fn varint(bytes: &mut &[u8]) -> u64 {
+            let mut value = 0;
+            for shift in (0..64).step_by(7) {
+                let byte = bytes[0];
+                *bytes = &bytes[1..];
+                value |= ((byte & 0x7f) as u64) << shift;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+            value
+        }
+        let mut fields = vec![];
+        while !bytes.is_empty() {
+            let tag = varint(&mut bytes);
+            assert_eq!(tag & 0x07, 2, "only length-delimited fields are expected");
+            let len = varint(&mut bytes) as usize;
+            fields.push((tag >> 3, bytes[..len].to_vec()));
+            bytes = &bytes[len..];
+        }
+        fields
+    }
+
+    #[test]
+    #[cfg(feature = "distribution")]
+    fn settle_funds_distribution_community_pool() {
+        let mut deps = instantiate_contract();
+        let env = mock_env();
+        let msg = ExecuteMsg::SetConfig {
+            nois_proxy: None,
+            manager: None,
+            lotto_nonce: None,
+            community_pool: None,
+            protocol_commission_percent: None,
+            creator_commission_percent: None,
+            is_paused: None,
+            randomness_source: None,
+            max_duration_seconds: None,
+            allowed_denoms: None,
+            community_pool_mode: Some(CommunityPoolMode::Distribution),
+            swap_router: None,
+            reserve_pays_nois_fee: None,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(MANAGER, &[]), msg).unwrap();
+
+        let msg = ExecuteMsg::CreateLotto {
+            ticket_price: Coin::new(100, "untrn"),
+            duration_seconds: 90,
+            number_of_winners: 1,
+            community_pool_percentage: 20,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
+        };
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
+        execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        for participant in ["participant-1", "participant-2"] {
+            let msg = ExecuteMsg::BuyTicket {
+                lotto_id: 0,
+                entropy: None,
+                vote: None,
+            };
+            let info = mock_info(participant, &[Coin::new(100, "untrn")]);
+            execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        }
+        let msg = ExecuteMsg::NoisReceive {
+            callback: NoisCallback {
+                job_id: "lotto-0".to_string(),
+                published: env.block.time.plus_seconds(100),
+                randomness: HexBinary::from([7; 32]),
+            },
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PROXY_ADDRESS, &[]),
+            msg,
+        )
+        .unwrap();
+        let msg = ExecuteMsg::Settle {
+            lotto_id: 0,
+            limit: None,
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), msg).unwrap();
+
+        let (type_url, value) = match &res.messages[0].msg {
+            CosmosMsg::Stargate { type_url, value } => (type_url, value),
+            msg => panic!("expected a stargate message, got {:?}", msg),
+        };
+        assert_eq!(
+            type_url,
+            "/cosmos.distribution.v1beta1.MsgFundCommunityPool"
+        );
+        let fields = decode_fields(value.as_slice());
+        assert_eq!(fields.len(), 2);
+        // repeated Coin amount = 1
+        assert_eq!(fields[0].0, 1);
+        assert_eq!(
+            decode_fields(&fields[0].1),
+            vec![(1, b"untrn".to_vec()), (2, b"40".to_vec())]
+        );
+        // string depositor = 2
+        assert_eq!(fields[1], (2, env.contract.address.as_bytes().to_vec()));
+    }
+
+    #[test]
+    fn beneficiary_receives_community_share() {
+        let mut deps = instantiate_contract();
+        let env = mock_env();
+        let add = ExecuteMsg::AddBeneficiary {
+            address: "grantee".to_string(),
+            name: "Grantee".to_string(),
+        };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(CREATOR, &[]),
+            add.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized);
+        execute(deps.as_mut(), env.clone(), mock_info(MANAGER, &[]), add).unwrap();
+        let BeneficiariesResponse { beneficiaries } = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::Beneficiaries {
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            beneficiaries,
+            vec![BeneficiaryResponse {
+                address: "grantee".to_string(),
+                name: "Grantee".to_string(),
+            }]
+        );
+
+        let create = |beneficiary: &str| ExecuteMsg::CreateLotto {
+            ticket_price: Coin::new(100, "untrn"),
+            duration_seconds: 90,
+            number_of_winners: 1,
+            community_pool_percentage: 20,
+            commitment: None,
+            beneficiary: Some(beneficiary.to_string()),
+            vote_weighting: None,
+            series: None,
+        };
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
+        let err =
+            execute(deps.as_mut(), env.clone(), info.clone(), create("stranger")).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::BeneficiaryNotRegistered {
+                address: "stranger".to_string()
+            }
+        );
+        execute(deps.as_mut(), env.clone(), info, create("grantee")).unwrap();
+        for participant in ["participant-1", "participant-2"] {
+            let msg = ExecuteMsg::BuyTicket {
+                lotto_id: 0,
+                entropy: None,
+                vote: None,
+            };
+            let info = mock_info(participant, &[Coin::new(100, "untrn")]);
+            execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        }
+
+        // Lottos that picked a removed beneficiary pay the community pool instead
+        let msg = ExecuteMsg::RemoveBeneficiary {
+            address: "grantee".to_string(),
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(MANAGER, &[]),
+            msg.clone(),
+        )
+        .unwrap();
+        let err = execute(deps.as_mut(), env.clone(), mock_info(MANAGER, &[]), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::BeneficiaryNotRegistered {
+                address: "grantee".to_string()
+            }
+        );
+        let lotto: LottoResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::Lotto { lotto_nonce: 0 },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(lotto.beneficiary, Some("grantee".to_string()));
+
+        let msg = ExecuteMsg::NoisReceive {
+            callback: NoisCallback {
+                job_id: "lotto-0".to_string(),
+                published: env.block.time.plus_seconds(100),
+                randomness: HexBinary::from([7; 32]),
+            },
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PROXY_ADDRESS, &[]),
+            msg,
+        )
+        .unwrap();
+        let msg = ExecuteMsg::Settle {
+            lotto_id: 0,
+            limit: None,
+        };
+        let res = execute(deps.as_mut(), env, mock_info("anyone", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages[0],
+            SubMsg::new(BankMsg::Send {
+                to_address: COM_POOL.to_string(),
+                amount: vec![Coin::new(40, "untrn")],
+            })
+        );
+    }
+
+    #[test]
+    fn votes_split_community_share() {
+        let mut deps = instantiate_contract();
+        let env = mock_env();
+        for address in ["grantee-a", "grantee-b"] {
+            let msg = ExecuteMsg::AddBeneficiary {
+                address: address.to_string(),
+                name: address.to_string(),
+            };
+            execute(deps.as_mut(), env.clone(), mock_info(MANAGER, &[]), msg).unwrap();
+        }
+        for vote_weighting in [None, Some(VoteWeighting::Quadratic)] {
+            let msg = ExecuteMsg::CreateLotto {
+                ticket_price: Coin::new(100, "untrn"),
+                duration_seconds: 90,
+                number_of_winners: 1,
+                community_pool_percentage: 20,
+                commitment: None,
+                beneficiary: None,
+                vote_weighting,
+                series: None,
+            };
+            let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
+            execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        }
+        let buy = |lotto_id, vote: Option<&str>| ExecuteMsg::BuyTicket {
+            lotto_id,
+            entropy: None,
+            vote: vote.map(str::to_string),
+        };
+        let info = mock_info("participant-1", &[Coin::new(100, "untrn")]);
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            info.clone(),
+            buy(0, Some("grantee-a")),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::VotingDisabled);
+        let err = execute(deps.as_mut(), env.clone(), info, buy(1, Some("stranger"))).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::BeneficiaryNotRegistered {
+                address: "stranger".to_string()
+            }
+        );
+
+        // 4 tickets weigh 2 and 1 ticket weighs 1 under quadratic weighting
+        for (participant, vote) in [
+            ("participant-1", Some("grantee-a")),
+            ("participant-1", Some("grantee-a")),
+            ("participant-1", Some("grantee-a")),
+            ("participant-1", Some("grantee-a")),
+            ("participant-2", Some("grantee-b")),
+            ("participant-3", None),
+        ] {
+            let info = mock_info(participant, &[Coin::new(100, "untrn")]);
+            execute(deps.as_mut(), env.clone(), info, buy(1, vote)).unwrap();
+        }
+        let votes: VotesResponse = from_binary(
+            &query(deps.as_ref(), env.clone(), QueryMsg::Votes { lotto_id: 1 }).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            votes,
+            VotesResponse {
+                lotto_id: 1,
+                vote_weighting: Some(VoteWeighting::Quadratic),
+                tallies: vec![
+                    VoteTally {
+                        beneficiary: "grantee-a".to_string(),
+                        tickets: 4,
+                        voters: 1,
+                        weight: Decimal::from_ratio(2u128, 1u128),
+                    },
+                    VoteTally {
+                        beneficiary: "grantee-b".to_string(),
+                        tickets: 1,
+                        voters: 1,
+                        weight: Decimal::one(),
+                    },
+                ],
+            }
+        );
+
+        let msg = ExecuteMsg::NoisReceive {
+            callback: NoisCallback {
+                job_id: "lotto-1".to_string(),
+                published: env.block.time.plus_seconds(100),
+                randomness: HexBinary::from([7; 32]),
+            },
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PROXY_ADDRESS, &[]),
+            msg,
+        )
+        .unwrap();
+        // the votes for a removed beneficiary go to the community pool
+        let msg = ExecuteMsg::RemoveBeneficiary {
+            address: "grantee-b".to_string(),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(MANAGER, &[]), msg).unwrap();
+        let msg = ExecuteMsg::Settle {
+            lotto_id: 1,
+            limit: None,
+        };
+        let res = execute(deps.as_mut(), env, mock_info("anyone", &[]), msg).unwrap();
+        // 20% of 600 split 2:1
+        assert_eq!(
+            res.messages[..2],
+            [
+                SubMsg::new(BankMsg::Send {
+                    to_address: "grantee-a".to_string(),
+                    amount: vec![Coin::new(80, "untrn")],
+                }),
+                SubMsg::new(BankMsg::Send {
+                    to_address: COM_POOL.to_string(),
+                    amount: vec![Coin::new(40, "untrn")],
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn protocol_withdrawals() {
+        let mut deps = instantiate_contract();
+        let env = mock_env();
+        for (denom, amount) in [("uatom", 50u128), ("untrn", 100)] {
+            PROTOCOL_BALANCES
+                .save(
+                    deps.as_mut().storage,
+                    denom.to_string(),
+                    &Uint128::new(amount),
+                )
+                .unwrap();
+            ESCROW
+                .save(
+                    deps.as_mut().storage,
+                    denom.to_string(),
+                    &Uint128::new(amount),
+                )
+                .unwrap();
+        }
+        deps.querier.update_balance(
+            MOCK_CONTRACT_ADDR,
+            vec![Coin::new(50, "uatom"), Coin::new(100, "untrn")],
+        );
+
+        let withdraw = |recipient: &str, coins: Vec<Coin>| ExecuteMsg::Withdraw {
+            recipient: recipient.to_string(),
+            coins,
+        };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(CREATOR, &[]),
+            withdraw("treasury", vec![Coin::new(10, "untrn")]),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized);
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(MANAGER, &[]),
+            withdraw("", vec![Coin::new(10, "untrn")]),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::Std(StdError::generic_err(
+                "Invalid input: human address too short for this mock implementation (must be >= 3)."
+            ))
+        );
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(MANAGER, &[]),
+            withdraw("treasury", vec![Coin::new(101, "untrn")]),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InsufficientProtocolBalance {
+                denom: "untrn".to_string()
+            }
+        );
+
+        // repeated denoms are merged into one transfer
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(MANAGER, &[]),
+            withdraw(
+                "treasury",
+                vec![Coin::new(30, "untrn"), Coin::new(10, "untrn")],
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: "treasury".to_string(),
+                amount: vec![Coin::new(40, "untrn")],
+            })]
+        );
+
+        let msg = ExecuteMsg::WithdrawAllDenoms {
+            recipient: "treasury-2".to_string(),
+        };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(MANAGER, &[]),
+            msg.clone(),
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: "treasury-2".to_string(),
+                amount: vec![Coin::new(50, "uatom"), Coin::new(60, "untrn")],
+            })]
+        );
+        let err = execute(deps.as_mut(), env.clone(), mock_info(MANAGER, &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::NothingToWithdraw);
+        let ProtocolBalancesResponse { balances } =
+            from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::ProtocolBalances {}).unwrap())
+                .unwrap();
+        assert_eq!(balances, vec![]);
+
+        let WithdrawalsResponse { withdrawals } = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::Withdrawals {
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            withdrawals,
+            vec![
+                WithdrawalResponse {
+                    id: 1,
+                    initiator: MANAGER.to_string(),
+                    recipient: "treasury-2".to_string(),
+                    coins: vec![Coin::new(50, "uatom"), Coin::new(60, "untrn")],
+                    time: env.block.time,
+                    height: env.block.height,
+                },
+                WithdrawalResponse {
+                    id: 0,
+                    initiator: MANAGER.to_string(),
+                    recipient: "treasury".to_string(),
+                    coins: vec![Coin::new(40, "untrn")],
+                    time: env.block.time,
+                    height: env.block.height,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn distribute_revenue_by_weight() {
+        let mut deps = instantiate_contract();
+        let env = mock_env();
+        for (denom, amount) in [("uatom", 7u128), ("untrn", 100)] {
+            PROTOCOL_BALANCES
+                .save(
+                    deps.as_mut().storage,
+                    denom.to_string(),
+                    &Uint128::new(amount),
+                )
+                .unwrap();
+            ESCROW
+                .save(
+                    deps.as_mut().storage,
+                    denom.to_string(),
+                    &Uint128::new(amount),
+                )
+                .unwrap();
+        }
+
+        let distribute = ExecuteMsg::DistributeRevenue {};
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            distribute.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::NoRevenueRecipients);
+
+        let recipient = |destination, weight| RevenueRecipientMsg {
+            destination,
+            weight,
+        };
+        let address = |address: &str| RevenueDestinationMsg::Address {
+            address: address.to_string(),
+        };
+        let msg = ExecuteMsg::SetRevenueRecipients {
+            recipients: vec![recipient(address("dev-fund"), 0)],
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info(MANAGER, &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidRevenueWeight);
+        let recipients = vec![
+            recipient(address("dev-fund"), 1),
+            recipient(RevenueDestinationMsg::NoisFeeReserve {}, 1),
+            recipient(address("treasury"), 2),
+        ];
+        let msg = ExecuteMsg::SetRevenueRecipients {
+            recipients: recipients.clone(),
+        };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(CREATOR, &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized);
+        execute(deps.as_mut(), env.clone(), mock_info(MANAGER, &[]), msg).unwrap();
+        let res: RevenueRecipientsResponse = from_binary(
+            &query(deps.as_ref(), env.clone(), QueryMsg::RevenueRecipients {}).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(res.recipients, recipients);
+
+        deps.querier.update_balance(
+            MOCK_CONTRACT_ADDR,
+            vec![Coin::new(7, "uatom"), Coin::new(100, "untrn")],
+        );
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            distribute,
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send {
+                    to_address: "dev-fund".to_string(),
+                    amount: vec![Coin::new(1, "uatom"), Coin::new(25, "untrn")],
+                }),
+                SubMsg::new(BankMsg::Send {
+                    to_address: "treasury".to_string(),
+                    amount: vec![Coin::new(3, "uatom"), Coin::new(50, "untrn")],
+                }),
+            ]
+        );
+        // the audit log records who triggered the distribution
+        let WithdrawalsResponse { withdrawals } = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::Withdrawals {
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(withdrawals.len(), 2);
+        assert!(withdrawals
+            .iter()
+            .all(|withdrawal| withdrawal.initiator == "anyone"));
+        // the rounding dust is left for the next distribution
+        let ProtocolBalancesResponse { balances } =
+            from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::ProtocolBalances {}).unwrap())
+                .unwrap();
+        assert_eq!(balances, vec![Coin::new(2, "uatom")]);
+        let NoisFeeReserveResponse { balances } =
+            from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::NoisFeeReserve {}).unwrap())
+                .unwrap();
+        assert_eq!(
+            balances,
+            vec![Coin::new(1, "uatom"), Coin::new(25, "untrn")]
+        );
+        let solvency: SolvencyResponse =
+            from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::Solvency {}).unwrap())
+                .unwrap();
+        assert_eq!(
+            solvency
+                .denoms
+                .iter()
+                .map(|denom| denom.escrowed.u128())
+                .collect::<Vec<_>>(),
+            vec![3, 25]
+        );
+
+        // the treasury share of the dust would use escrowed funds
+        deps.querier
+            .update_balance(MOCK_CONTRACT_ADDR, vec![Coin::new(2, "uatom")]);
+        let err = execute(
+            deps.as_mut(),
+            env,
+            mock_info("anyone", &[]),
+            ExecuteMsg::DistributeRevenue {},
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::EscrowedFunds {
+                denom: "uatom".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn convert_revenue_through_swap_router() {
+        let participants = ["participant-1", "participant-2", "participant-3"];
+        let mut app = App::new(|router, _, storage| {
+            for participant in participants {
+                router
+                    .bank
+                    .init_balance(
+                        storage,
+                        &Addr::unchecked(participant),
+                        vec![Coin::new(100, "untrn")],
+                    )
+                    .unwrap();
+            }
+            router
+                .bank
+                .init_balance(
+                    storage,
+                    &Addr::unchecked("liquidity"),
+                    vec![Coin::new(1000, "uusdc")],
+                )
+                .unwrap();
+        });
+
+        // Pays out twice the offered amount in the asked denom, whatever `min_out` says
+        let swap_router = ContractWrapper::new(
+            |_: DepsMut, _: Env, info: MessageInfo, msg: SwapRouterExecuteMsg| {
+                let SwapRouterExecuteMsg::Swap { ask_denom, .. } = msg;
+                Ok::<_, StdError>(Response::new().add_message(BankMsg::Send {
+                    to_address: info.sender.into_string(),
+                    amount: vec![Coin::new(info.funds[0].amount.u128() * 2, ask_denom)],
+                }))
+            },
+            |_: DepsMut, _: Env, _: MessageInfo, _: Empty| Ok::<_, StdError>(Response::new()),
+            |_: Deps, _: Env, _: Empty| to_binary(&Empty {}),
+        );
+        let router_code_id = app.store_code(Box::new(swap_router));
+        let router_addr = app
+            .instantiate_contract(
+                router_code_id,
+                Addr::unchecked(MANAGER),
+                &Empty {},
+                &[],
+                "router",
+                None,
+            )
+            .unwrap();
+        app.send_tokens(
+            Addr::unchecked("liquidity"),
+            router_addr.clone(),
+            &[Coin::new(1000, "uusdc")],
+        )
+        .unwrap();
+        let code_id = app.store_code(Box::new(
+            ContractWrapper::new(execute, instantiate, query).with_reply(reply),
+        ));
+        let lotto_addr = app
+            .instantiate_contract(
+                code_id,
+                Addr::unchecked(MANAGER),
+                &InstantiateMsg {
+                    manager: MANAGER.to_string(),
+                    nois_proxy: PROXY_ADDRESS.to_string(),
+                    community_pool: COM_POOL.to_string(),
+                    protocol_commission_percent: 5,
+                    creator_commission_percent: 15,
+                    randomness_source: Some(RandomnessSource::Mock),
+                    max_duration_seconds: None,
+                    allowed_denoms: None,
+                    community_pool_mode: None,
+                    swap_router: Some(router_addr.to_string()),
+                },
+                &[],
+                "lotto",
+                None,
+            )
+            .unwrap();
+
+        // a lotto earns the protocol 15untrn
+        app.execute_contract(
+            Addr::unchecked(CREATOR),
+            lotto_addr.clone(),
+            &ExecuteMsg::CreateLotto {
+                ticket_price: Coin::new(100, "untrn"),
+                duration_seconds: 90,
+                number_of_winners: 1,
+                community_pool_percentage: 20,
+                commitment: None,
+                beneficiary: None,
+                vote_weighting: None,
+                series: None,
+            },
+            &[],
+        )
+        .unwrap();
+        for participant in participants {
+            app.execute_contract(
+                Addr::unchecked(participant),
+                lotto_addr.clone(),
+                &ExecuteMsg::BuyTicket {
+                    lotto_id: 0,
+                    entropy: None,
+                    vote: None,
+                },
+                &[Coin::new(100, "untrn")],
+            )
+            .unwrap();
+        }
+        app.update_block(|block| block.time = block.time.plus_seconds(91));
+        for msg in [
+            ExecuteMsg::ReceiveMockRandomness {
+                job_id: "lotto-0".to_string(),
+            },
+            ExecuteMsg::Settle {
+                lotto_id: 0,
+                limit: None,
+            },
+        ] {
+            app.execute_contract(Addr::unchecked("anyone"), lotto_addr.clone(), &msg, &[])
+                .unwrap();
+        }
+
+        let convert = |min_out| ExecuteMsg::ConvertRevenue {
+            denom: "untrn".to_string(),
+            min_out: Coin::new(min_out, "uusdc"),
+        };
+        let err = app
+            .execute_contract(
+                Addr::unchecked("anyone"),
+                lotto_addr.clone(),
+                &convert(30),
+                &[],
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::Unauthorized
+        );
+        // the reply rejects a swap returning less than asked
+        let err = app
+            .execute_contract(
+                Addr::unchecked(MANAGER),
+                lotto_addr.clone(),
+                &convert(31),
+                &[],
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::SwapOutputTooLow {
+                min_out: Coin::new(31, "uusdc")
+            }
+        );
+        app.execute_contract(
+            Addr::unchecked(MANAGER),
+            lotto_addr.clone(),
+            &convert(30),
+            &[],
+        )
+        .unwrap();
+
+        let ProtocolBalancesResponse { balances } = app
+            .wrap()
+            .query_wasm_smart(&lotto_addr, &QueryMsg::ProtocolBalances {})
+            .unwrap();
+        assert_eq!(balances, vec![Coin::new(30, "uusdc")]);
+
+        // without a router there is nothing to convert through
+        app.execute_contract(
+            Addr::unchecked(MANAGER),
+            lotto_addr.clone(),
+            &ExecuteMsg::SetConfig {
+                nois_proxy: None,
+                manager: None,
+                lotto_nonce: None,
+                community_pool: None,
+                protocol_commission_percent: None,
+                creator_commission_percent: None,
+                is_paused: None,
+                randomness_source: None,
+                max_duration_seconds: None,
+                allowed_denoms: None,
+                community_pool_mode: None,
+                swap_router: Some(SwapRouterMsg::Clear {}),
+                reserve_pays_nois_fee: None,
+            },
+            &[],
+        )
+        .unwrap();
+        let config: ConfigResponse = app
+            .wrap()
+            .query_wasm_smart(&lotto_addr, &QueryMsg::Config {})
+            .unwrap();
+        assert_eq!(config.swap_router, None);
+        let err = app
+            .execute_contract(
+                Addr::unchecked(MANAGER),
+                lotto_addr.clone(),
+                &ExecuteMsg::ConvertRevenue {
+                    denom: "uusdc".to_string(),
+                    min_out: Coin::new(1, "untrn"),
+                },
+                &[],
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::NoSwapRouter
+        );
+
+        let solvency: SolvencyResponse = app
+            .wrap()
+            .query_wasm_smart(&lotto_addr, &QueryMsg::Solvency {})
+            .unwrap();
+        assert_eq!(
+            solvency.denoms,
+            vec![
+                DenomSolvency {
+                    denom: "untrn".to_string(),
+                    balance: Uint128::zero(),
+                    escrowed: Uint128::zero(),
+                    surplus: Uint128::zero(),
+                    shortfall: Uint128::zero(),
+                    untracked_payouts: Uint128::zero(),
+                },
+                DenomSolvency {
+                    denom: "uusdc".to_string(),
+                    balance: Uint128::new(30),
+                    escrowed: Uint128::new(30),
+                    surplus: Uint128::zero(),
+                    shortfall: Uint128::zero(),
+                    untracked_payouts: Uint128::zero(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn hooks_notify_and_fail_in_isolation() {
+        let participants = ["participant-1", "participant-2"];
+        let mut app = App::new(|router, _, storage| {
+            for participant in participants {
+                router
+                    .bank
+                    .init_balance(
+                        storage,
+                        &Addr::unchecked(participant),
+                        vec![Coin::new(100, "untrn")],
+                    )
+                    .unwrap();
+            }
+        });
+
+        // Echoes the kind of event it was notified of
+        let echo_hook = ContractWrapper::new(
+            |_: DepsMut, _: Env, _: MessageInfo, msg: HookExecuteMsg| {
+                let HookExecuteMsg::LottoHook(hook) = msg;
+                let kind = match hook {
+                    LottoHook::LottoCreated { .. } => "lotto_created",
+                    LottoHook::TicketBought { .. } => "ticket_bought",
+                    LottoHook::LottoSettled { .. } => "lotto_settled",
+                };
+                Ok::<_, StdError>(Response::new().add_attribute("hook", kind))
+            },
+            |_: DepsMut, _: Env, _: MessageInfo, _: Empty| Ok::<_, StdError>(Response::new()),
+            |_: Deps, _: Env, _: Empty| to_binary(&Empty {}),
+        );
+        let failing_hook = ContractWrapper::new(
+            |_: DepsMut, _: Env, _: MessageInfo, _: HookExecuteMsg| {
+                Err::<Response, _>(StdError::generic_err("hook is broken"))
+            },
+            |_: DepsMut, _: Env, _: MessageInfo, _: Empty| Ok::<_, StdError>(Response::new()),
+            |_: Deps, _: Env, _: Empty| to_binary(&Empty {}),
+        );
+        let echo_code_id = app.store_code(Box::new(echo_hook));
+        let failing_code_id = app.store_code(Box::new(failing_hook));
+        let mut hooks = vec![];
+        for (code_id, label) in [(echo_code_id, "echo"), (failing_code_id, "failing")] {
+            hooks.push(
+                app.instantiate_contract(
+                    code_id,
+                    Addr::unchecked(MANAGER),
+                    &Empty {},
+                    &[],
+                    label,
+                    None,
+                )
+                .unwrap(),
+            );
+        }
+        let code_id = app.store_code(Box::new(
+            ContractWrapper::new(execute, instantiate, query).with_reply(reply),
+        ));
+        let lotto_addr = app
+            .instantiate_contract(
+                code_id,
+                Addr::unchecked(MANAGER),
+                &InstantiateMsg {
+                    manager: MANAGER.to_string(),
+                    nois_proxy: PROXY_ADDRESS.to_string(),
+                    community_pool: COM_POOL.to_string(),
+                    protocol_commission_percent: 5,
+                    creator_commission_percent: 15,
+                    randomness_source: Some(RandomnessSource::Mock),
+                    max_duration_seconds: None,
+                    allowed_denoms: None,
+                    community_pool_mode: None,
+                    swap_router: None,
+                },
+                &[],
+                "lotto",
+                None,
+            )
+            .unwrap();
+
+        let err = app
+            .execute_contract(
+                Addr::unchecked(CREATOR),
+                lotto_addr.clone(),
+                &ExecuteMsg::AddHook {
+                    contract: hooks[0].to_string(),
+                },
+                &[],
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::Unauthorized
+        );
+        for hook in &hooks {
+            app.execute_contract(
+                Addr::unchecked(MANAGER),
+                lotto_addr.clone(),
+                &ExecuteMsg::AddHook {
+                    contract: hook.to_string(),
+                },
+                &[],
+            )
+            .unwrap();
+        }
+        let HooksResponse { hooks: registered } = app
+            .wrap()
+            .query_wasm_smart(&lotto_addr, &QueryMsg::Hooks {})
+            .unwrap();
+        assert_eq!(registered.len(), 2);
+
+        // Every notification reaches the echo hook while the failing one is only reported
+        let assert_notified = |res: &AppResponse, kind: &str| {
+            let attribute = |key: &str, value: &str| {
+                res.events
+                    .iter()
+                    .flat_map(|event| &event.attributes)
+                    .any(|attr| attr.key == key && attr.value == value)
+            };
+            assert!(attribute("hook", kind));
+            assert!(attribute("action", "hook_failed"));
+        };
+        let res = app
+            .execute_contract(
+                Addr::unchecked(CREATOR),
+                lotto_addr.clone(),
+                &ExecuteMsg::CreateLotto {
+                    ticket_price: Coin::new(100, "untrn"),
+                    duration_seconds: 90,
+                    number_of_winners: 1,
+                    community_pool_percentage: 20,
+                    commitment: None,
+                    beneficiary: None,
+                    vote_weighting: None,
+                    series: None,
+                },
+                &[],
+            )
+            .unwrap();
+        assert_notified(&res, "lotto_created");
+        for participant in participants {
+            let res = app
+                .execute_contract(
+                    Addr::unchecked(participant),
+                    lotto_addr.clone(),
+                    &ExecuteMsg::BuyTicket {
+                        lotto_id: 0,
+                        entropy: None,
+                        vote: None,
+                    },
+                    &[Coin::new(100, "untrn")],
+                )
+                .unwrap();
+            assert_notified(&res, "ticket_bought");
+        }
+        app.update_block(|block| block.time = block.time.plus_seconds(91));
+        app.execute_contract(
+            Addr::unchecked("anyone"),
+            lotto_addr.clone(),
+            &ExecuteMsg::ReceiveMockRandomness {
+                job_id: "lotto-0".to_string(),
+            },
+            &[],
+        )
+        .unwrap();
+        let res = app
+            .execute_contract(
+                Addr::unchecked("anyone"),
+                lotto_addr.clone(),
+                &ExecuteMsg::Settle {
+                    lotto_id: 0,
+                    limit: None,
+                },
+                &[],
+            )
+            .unwrap();
+        assert_notified(&res, "lotto_settled");
+        let lotto: LottoResponse = app
+            .wrap()
+            .query_wasm_smart(&lotto_addr, &QueryMsg::Lotto { lotto_nonce: 0 })
+            .unwrap();
+        assert_eq!(lotto.status, LottoStatus::Settled);
+
+        for hook in &hooks {
+            app.execute_contract(
+                Addr::unchecked(MANAGER),
+                lotto_addr.clone(),
+                &ExecuteMsg::RemoveHook {
+                    contract: hook.to_string(),
+                },
+                &[],
+            )
+            .unwrap();
+        }
+        let err = app
+            .execute_contract(
+                Addr::unchecked(MANAGER),
+                lotto_addr.clone(),
+                &ExecuteMsg::RemoveHook {
+                    contract: hooks[0].to_string(),
+                },
+                &[],
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::HookNotRegistered {
+                contract: hooks[0].to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn typed_events() {
+        let mut deps = instantiate_contract();
+        let env = mock_env();
+        let event = |res: &Response, ty: &str| {
+            res.events
+                .iter()
+                .find(|event| event.ty == ty)
+                .unwrap_or_else(|| panic!("no {ty} event"))
+                .clone()
+        };
+
+        let msg = ExecuteMsg::CreateLotto {
+            ticket_price: Coin::new(100, "untrn"),
+            duration_seconds: 90,
+            number_of_winners: 1,
+            community_pool_percentage: 20,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
+        };
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
+        let res = execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        assert_eq!(
+            event(&res, events::LOTTO_CREATED),
+            Event::new("lotto_created").add_attributes(vec![
+                ("lotto_id", "0".to_string()),
+                ("creator", CREATOR.to_string()),
+                ("ticket_price", "100untrn".to_string()),
+                (
+                    "expiration",
+                    env.block.time.plus_seconds(90).nanos().to_string()
+                ),
+                ("number_of_winners", "1".to_string()),
+                ("community_pool_percentage", "20".to_string()),
+                ("nois_fee", format!("{NOIS_PRICE}unois")),
+            ])
+        );
+
+        for (i, participant) in ["participant-1", "participant-2"].iter().enumerate() {
+            let msg = ExecuteMsg::BuyTicket {
+                lotto_id: 0,
+                entropy: None,
+                vote: None,
+            };
+            let info = mock_info(participant, &[Coin::new(100, "untrn")]);
+            let res = execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+            assert_eq!(
+                event(&res, events::TICKET_PURCHASED),
+                Event::new("ticket_purchased").add_attributes(vec![
+                    ("lotto_id", "0".to_string()),
+                    ("buyer", participant.to_string()),
+                    ("ticket_price", "100untrn".to_string()),
+                    ("tickets_sold", (i + 1).to_string()),
+                    ("balance", format!("{}untrn", 100 * (i + 1))),
+                ])
+            );
+        }
+
+        let msg = ExecuteMsg::NoisReceive {
+            callback: NoisCallback {
+                job_id: "lotto-0".to_string(),
+                published: env.block.time.plus_seconds(100),
+                randomness: HexBinary::from([7; 32]),
+            },
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PROXY_ADDRESS, &[]),
+            msg,
+        )
+        .unwrap();
+        let msg = ExecuteMsg::Settle {
+            lotto_id: 0,
+            limit: None,
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), msg).unwrap();
+        let lotto: LottoResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::Lotto { lotto_nonce: 0 },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            event(&res, events::LOTTO_SETTLED),
+            Event::new("lotto_settled").add_attributes(vec![
+                ("lotto_id", "0".to_string()),
+                ("winners", lotto.winners.unwrap()[0].to_string()),
+                ("prize_per_winner", "120untrn".to_string()),
+                ("creator_commission", "30untrn".to_string()),
+                ("protocol_commission", "10untrn".to_string()),
+                ("community_pool_share", "40untrn".to_string()),
+            ])
+        );
+
+        deps.querier
+            .update_balance(MOCK_CONTRACT_ADDR, vec![Coin::new(10, "untrn")]);
+        let msg = ExecuteMsg::Withdraw {
+            recipient: "treasury".to_string(),
+            coins: vec![Coin::new(10, "untrn")],
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info(MANAGER, &[]), msg).unwrap();
+        assert_eq!(
+            event(&res, events::WITHDRAWAL),
+            Event::new("withdrawal").add_attributes(vec![
+                ("id", "0"),
+                ("initiator", MANAGER),
+                ("recipient", "treasury"),
+                ("amount", "10untrn"),
+            ])
+        );
+
+        let msg = ExecuteMsg::SetConfig {
+            nois_proxy: None,
+            manager: None,
+            lotto_nonce: None,
+            community_pool: None,
+            protocol_commission_percent: Some(10),
+            creator_commission_percent: None,
+            is_paused: None,
+            randomness_source: None,
+            max_duration_seconds: None,
+            allowed_denoms: Some(vec!["untrn".to_string(), "uatom".to_string()]),
+            community_pool_mode: None,
+            swap_router: None,
+            reserve_pays_nois_fee: None,
+        };
+        let res = execute(deps.as_mut(), env, mock_info(MANAGER, &[]), msg).unwrap();
+        assert_eq!(
+            event(&res, events::CONFIG_UPDATED),
+            Event::new("config_updated").add_attributes(vec![
+                ("manager", MANAGER),
+                ("nois_proxy", PROXY_ADDRESS),
+                ("lotto_nonce", "1"),
+                ("community_pool", COM_POOL),
+                ("protocol_commission_percent", "10"),
+                ("creator_commission_percent", "15"),
+                ("is_paused", "false"),
+                ("randomness_source", "nois"),
+                ("max_duration_seconds", "2592000"),
+                ("allowed_denoms", "untrn,uatom"),
+                ("community_pool_mode", "address"),
+                ("reserve_pays_nois_fee", "false"),
+            ])
+        );
+    }
+
+    #[test]
+    fn buy_ticket_over_ibc() {
+        let mut deps = instantiate_contract();
+        let mut env = mock_env();
+        // The IBC-hooks account gets the bech32 prefix of the contract
+        env.contract.address =
+            Addr::unchecked("neutron1suhgf5svhu4usrurvxzlgn54ksxmn8gljarjtxqnapv8kjnp4nrstdxvff");
+        let msg = ExecuteMsg::CreateLotto {
+            ticket_price: Coin::new(100, "ibc/atom"),
+            duration_seconds: 90,
+            number_of_winners: 3,
+            community_pool_percentage: 20,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
+        };
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
+        execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+        let buy = |channel: &str, original_sender: &str, payout| ExecuteMsg::BuyTicketIbc {
+            lotto_id: 0,
+            channel: channel.to_string(),
+            original_sender: original_sender.to_string(),
+            entropy: None,
+            vote: None,
+            payout,
+        };
+        let alice = ibc_hooks_sender(&env.contract.address, "channel-0", "cosmos1alice").unwrap();
+        assert!(alice.starts_with("neutron1"));
+        assert_ne!(
+            alice,
+            ibc_hooks_sender(&env.contract.address, "channel-1", "cosmos1alice").unwrap()
+        );
+
+        // Only the IBC-hooks account of the original sender can buy in its name
+        for (sender, channel) in [("anyone", "channel-0"), (alice.as_str(), "channel-1")] {
+            let err = execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info(sender, &[Coin::new(100, "ibc/atom")]),
+                buy(channel, "cosmos1alice", CrossChainPayoutMsg::Payback {}),
+            )
+            .unwrap_err();
+            assert_eq!(err, ContractError::InvalidIbcHooksSender);
+        }
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(&alice, &[Coin::new(100, "ibc/atom")]),
+            buy("channel-0", "cosmos1alice", CrossChainPayoutMsg::Payback {}),
+        )
+        .unwrap();
+        // the payout chosen with the first ticket stays
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(&alice, &[Coin::new(100, "ibc/atom")]),
+            buy(
+                "channel-0",
+                "cosmos1alice",
+                CrossChainPayoutMsg::Local {
+                    address: "mallory".to_string(),
+                },
+            ),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::CrossChainPayoutMismatch);
+        let bob = ibc_hooks_sender(&env.contract.address, "channel-5", "osmo1bob").unwrap();
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(&bob, &[Coin::new(100, "ibc/atom")]),
+            buy(
+                "channel-5",
+                "osmo1bob",
+                CrossChainPayoutMsg::Local {
+                    address: "bob".to_string(),
+                },
+            ),
+        )
+        .unwrap();
+
+        // A counterparty chain can't pass off a local participant as its sender
+        let victim = "neutron1victim";
+        let msg = ExecuteMsg::BuyTicket {
+            lotto_id: 0,
+            entropy: None,
+            vote: None,
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(victim, &[Coin::new(100, "ibc/atom")]),
+            msg,
+        )
+        .unwrap();
+        for forged in [victim, "NEUTRON1VICTIM"] {
+            let mallory = ibc_hooks_sender(&env.contract.address, "channel-6", forged).unwrap();
+            let err = execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info(&mallory, &[Coin::new(100, "ibc/atom")]),
+                buy("channel-6", forged, CrossChainPayoutMsg::Payback {}),
+            )
+            .unwrap_err();
+            assert_eq!(
+                err,
+                ContractError::LocalOriginalSender {
+                    original_sender: forged.to_string()
+                }
+            );
+        }
+        let lotto: LottoResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::Lotto { lotto_nonce: 0 },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        // the tickets bought over IBC belong to the IBC-hooks accounts
+        assert_eq!(
+            lotto.participants,
+            vec![alice.clone(), bob, victim.to_string()]
+        );
+
+        let msg = ExecuteMsg::NoisReceive {
+            callback: NoisCallback {
+                job_id: "lotto-0".to_string(),
+                published: env.block.time.plus_seconds(100),
+                randomness: HexBinary::from([7; 32]),
+            },
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PROXY_ADDRESS, &[]),
+            msg,
+        )
+        .unwrap();
+        let msg = ExecuteMsg::Settle {
+            lotto_id: 0,
+            limit: None,
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), msg).unwrap();
+        // Everyone wins, alice is paid back over IBC, bob and the victim on this chain
+        let msgs: Vec<CosmosMsg> = res.messages.into_iter().map(|sub| sub.msg).collect();
+        assert!(msgs.contains(&CosmosMsg::Ibc(IbcMsg::Transfer {
+            channel_id: "channel-0".to_string(),
+            to_address: "cosmos1alice".to_string(),
+            amount: Coin::new(60, "ibc/atom"),
+            timeout: IbcTimeout::with_timestamp(
+                env.block.time.plus_seconds(IBC_TRANSFER_TIMEOUT_SECONDS)
+            ),
+        })));
+        assert!(msgs.contains(&CosmosMsg::Bank(BankMsg::Send {
+            to_address: "bob".to_string(),
+            amount: vec![Coin::new(60, "ibc/atom")],
+        })));
+        assert!(msgs.contains(&CosmosMsg::Bank(BankMsg::Send {
+            to_address: victim.to_string(),
+            amount: vec![Coin::new(60, "ibc/atom")],
+        })));
+
+        // A failed payback is claimed back over the channel the tickets were bought from
+        let failed = Reply {
+            id: IBC_PAYOUT_REPLY_ID,
+            result: SubMsgResult::Err("channel not found".to_string()),
+        };
+        reply(deps.as_mut(), env.clone(), failed).unwrap();
+        let msg = ExecuteMsg::Claim {
+            address: Some(alice),
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::reply_always(
+                IbcMsg::Transfer {
+                    channel_id: "channel-0".to_string(),
+                    to_address: "cosmos1alice".to_string(),
+                    amount: Coin::new(60, "ibc/atom"),
+                    timeout: IbcTimeout::with_timestamp(
+                        env.block.time.plus_seconds(IBC_TRANSFER_TIMEOUT_SECONDS)
+                    ),
+                },
+                IBC_PAYOUT_REPLY_ID,
+            )]
+        );
+    }
+
+    #[test]
+    fn ibc_payouts_and_claims() {
+        let mut deps = instantiate_contract();
+        let env = mock_env();
+        let msg = ExecuteMsg::CreateLotto {
+            ticket_price: Coin::new(100, "untrn"),
+            duration_seconds: 90,
+            number_of_winners: 2,
+            community_pool_percentage: 20,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
+        };
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
+        execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+        let set_channel =
+            |lotto_id, channel: &str, remote_address: &str| ExecuteMsg::SetPayoutChannel {
+                lotto_id,
+                channel: channel.to_string(),
+                remote_address: remote_address.to_string(),
+            };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            set_channel(None, "transfer", "cosmos1alice"),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidPayoutChannel {
+                channel: "transfer".to_string()
+            }
+        );
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            set_channel(Some(1), "channel-1", "cosmos1alice"),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::LottoNotFound);
+        // bob's channel for the lotto wins over the one for all lottos
+        for (sender, msg) in [
+            ("alice", set_channel(None, "channel-1", "cosmos1alice")),
+            ("bob", set_channel(None, "channel-9", "juno1bob")),
+            ("bob", set_channel(Some(0), "channel-2", "osmo1bob")),
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info(sender, &[]), msg).unwrap();
+        }
+        let PayoutChannelResponse { payout_channel, .. } = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::PayoutChannel {
+                    address: "bob".to_string(),
+                    lotto_id: Some(0),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            payout_channel,
+            Some(PayoutChannel {
+                channel: "channel-2".to_string(),
+                remote_address: "osmo1bob".to_string(),
+            })
+        );
+
+        for participant in ["alice", "bob"] {
+            let msg = ExecuteMsg::BuyTicket {
+                lotto_id: 0,
+                entropy: None,
+                vote: None,
+            };
+            let info = mock_info(participant, &[Coin::new(100, "untrn")]);
+            execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        }
+        let msg = ExecuteMsg::NoisReceive {
+            callback: NoisCallback {
+                job_id: "lotto-0".to_string(),
+                published: env.block.time.plus_seconds(100),
+                randomness: HexBinary::from([7; 32]),
+            },
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PROXY_ADDRESS, &[]),
+            msg,
+        )
+        .unwrap();
+        let msg = ExecuteMsg::Settle {
+            lotto_id: 0,
+            limit: None,
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), msg).unwrap();
+        let transfer = |channel: &str, to_address: &str| {
+            SubMsg::reply_always(
+                IbcMsg::Transfer {
+                    channel_id: channel.to_string(),
+                    to_address: to_address.to_string(),
+                    amount: Coin::new(60, "untrn"),
+                    timeout: IbcTimeout::with_timestamp(
+                        env.block.time.plus_seconds(IBC_TRANSFER_TIMEOUT_SECONDS),
+                    ),
+                },
+                IBC_PAYOUT_REPLY_ID,
+            )
+        };
+        // both win, the prizes are paid in the order of the winners
+        let lotto: LottoResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::Lotto { lotto_nonce: 0 },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let winners = lotto.winners.unwrap();
+        let payouts: Vec<SubMsg> = winners
+            .iter()
+            .map(|winner| match winner.as_str() {
+                "alice" => transfer("channel-1", "cosmos1alice"),
+                _ => transfer("channel-2", "osmo1bob"),
+            })
+            .collect();
+        assert_eq!(res.messages[2..], payouts);
+
+        // the first transfer is sent with sequence 7 and then times out,
+        // the second one can't even be sent
+        let sent = Reply {
+            id: IBC_PAYOUT_REPLY_ID,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: Some(
+                    anybuf::Anybuf::new()
+                        .append_uint64(1, 7)
+                        .append_string(2, "channel-1")
+                        .into_vec()
+                        .into(),
+                ),
+            }),
+        };
+        reply(deps.as_mut(), env.clone(), sent).unwrap();
+        let failed = Reply {
+            id: IBC_PAYOUT_REPLY_ID,
+            result: SubMsgResult::Err("channel not found".to_string()),
+        };
+        reply(deps.as_mut(), env.clone(), failed).unwrap();
+        let request = |sequence| RequestPacket {
+            sequence: Some(sequence),
+            source_port: Some("transfer".to_string()),
+            source_channel: Some(
+                match winners[0].as_str() {
+                    "alice" => "channel-1",
+                    _ => "channel-2",
+                }
+                .to_string(),
+            ),
+            destination_port: None,
+            destination_channel: None,
+            data: None,
+            timeout_height: None,
+            timeout_timestamp: None,
+        };
+        // Neutron's transfer module calls back the contract for every ICS20 packet it sent,
+        // including the MsgTransfer wasmd dispatches for an IbcMsg::Transfer.
+        // Unknown packets are ignored.
+        let res = sudo(
+            deps.as_mut(),
+            env.clone(),
+            SudoMsg::Timeout {
+                request: request(8),
+            },
+        )
+        .unwrap();
+        assert!(res.attributes.is_empty());
+        sudo(
+            deps.as_mut(),
+            env.clone(),
+            SudoMsg::Timeout {
+                request: request(7),
+            },
+        )
+        .unwrap();
+        for winner in &winners {
+            let ClaimableResponse { coins, .. } = from_binary(
+                &query(
+                    deps.as_ref(),
+                    env.clone(),
+                    QueryMsg::Claimable {
+                        address: winner.to_string(),
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap();
+            assert_eq!(coins, vec![Coin::new(60, "untrn")]);
+        }
+
+        // an address in the wrong case is rejected instead of owning nothing
+        for msg in [
+            QueryMsg::Claimable {
+                address: "ALICE".to_string(),
+            },
+            QueryMsg::PayoutChannel {
+                address: "ALICE".to_string(),
+                lotto_id: None,
+            },
+        ] {
+            query(deps.as_ref(), env.clone(), msg).unwrap_err();
+        }
+
+        // claims go over the channel registered for all lottos, whoever sends them
+        let msg = ExecuteMsg::Claim {
+            address: Some("bob".to_string()),
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), msg).unwrap();
+        assert_eq!(res.messages, vec![transfer("channel-9", "juno1bob")]);
+        let msg = ExecuteMsg::Claim { address: None };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
+        assert_eq!(res.messages, vec![transfer("channel-1", "cosmos1alice")]);
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            ExecuteMsg::Claim { address: None },
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::NothingToClaim);
+
+        // the claimed transfers go on untracked without a sequence in their responses
+        let untracked = Reply {
+            id: IBC_PAYOUT_REPLY_ID,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: None,
+            }),
+        };
+        for _ in 0..2 {
+            let res = reply(deps.as_mut(), env.clone(), untracked.clone()).unwrap();
+            assert_eq!(
+                res.attributes[0],
+                Attribute::new("action", "ibc_payout_untracked")
+            );
+        }
+        let err = reply(deps.as_mut(), env.clone(), untracked).unwrap_err();
+        assert_eq!(err, ContractError::UnexpectedIbcPayoutReply);
+        // a failure of theirs would only show as surplus, the solvency query counts them
+        let solvency: SolvencyResponse =
+            from_binary(&query(deps.as_ref(), env, QueryMsg::Solvency {}).unwrap()).unwrap();
+        assert_eq!(solvency.denoms[0].untracked_payouts, Uint128::new(120));
+    }
+
+    #[test]
+    fn buy_ticket_with_signature() {
+        let mut deps = instantiate_contract();
+        let mut env = mock_env();
+        // The buyer address gets the bech32 prefix of the contract
+        env.contract.address =
+            Addr::unchecked("neutron1suhgf5svhu4usrurvxzlgn54ksxmn8gljarjtxqnapv8kjnp4nrstdxvff");
+        let msg = ExecuteMsg::CreateLotto {
+            ticket_price: Coin::new(100, "untrn"),
+            duration_seconds: 90,
+            number_of_winners: 1,
+            community_pool_percentage: 20,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
+        };
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
+        execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+        let signing_key = SigningKey::from_bytes(&[7; 32]).unwrap();
+        // compressed secp256k1 public key
+        let pubkey = Binary::from(signing_key.verifying_key().to_bytes().as_slice());
+        let buyer = pubkey_address(&env.contract.address, &pubkey).unwrap();
+        assert!(buyer.as_str().starts_with("neutron1"));
+        let msg = ExecuteMsg::DepositBalance {
+            recipient: Some(buyer.to_string()),
+        };
+        let info = mock_info("sponsor", &[Coin::new(150, "untrn")]);
+        execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+        let deadline = env.block.time.plus_seconds(60);
+        let sign = |lotto_id, nonce| {
+            let authorization = TicketAuthorization {
+                contract: env.contract.address.to_string(),
+                chain_id: env.block.chain_id.clone(),
+                lotto_id,
+                nonce,
+                deadline,
+            };
+            let signature: Signature =
+                signing_key.sign(to_binary(&authorization).unwrap().as_slice());
+            Binary::from(signature.as_ref())
+        };
+        let buy = |lotto_id, nonce, signature, deadline| ExecuteMsg::BuyTicketWithSignature {
+            lotto_id,
+            buyer_pubkey: pubkey.clone(),
+            signature,
+            nonce,
+            deadline,
+        };
+        let relayer = mock_info("relayer", &[]);
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            relayer.clone(),
+            buy(0, 0, sign(0, 0), deadline),
+        )
+        .unwrap();
+        let lotto: LottoResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::Lotto { lotto_nonce: 0 },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(lotto.participants, vec![buyer.clone()]);
+        let NonceResponse { nonce, .. } = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::Nonce {
+                    address: buyer.to_string(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(nonce, 1);
+        // the nonce of an address in the wrong case isn't silently zero
+        let msg = QueryMsg::Nonce {
+            address: buyer.as_str().to_uppercase(),
+        };
+        query(deps.as_ref(), env.clone(), msg).unwrap_err();
+
+        // replayed, tampered with and expired authorizations
+        for (msg, expected) in [
+            (
+                buy(0, 0, sign(0, 0), deadline),
+                ContractError::InvalidNonce { expected: 1 },
+            ),
+            (
+                buy(1, 1, sign(0, 1), deadline),
+                ContractError::InvalidSignature,
+            ),
+            (
+                buy(0, 1, sign(0, 1), deadline.plus_seconds(1)),
+                ContractError::InvalidSignature,
+            ),
+        ] {
+            let err = execute(deps.as_mut(), env.clone(), relayer.clone(), msg).unwrap_err();
+            assert_eq!(err, expected);
+        }
+        let mut late = env.clone();
+        late.block.time = deadline.plus_seconds(1);
+        let err = execute(
+            deps.as_mut(),
+            late,
+            relayer.clone(),
+            buy(0, 1, sign(0, 1), deadline),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::AuthorizationExpired);
+
+        // 50untrn are left, not enough for a second ticket
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            relayer,
+            buy(0, 1, sign(0, 1), deadline),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InsufficientBalance {
+                denom: "untrn".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn player_balances() {
+        let mut deps = instantiate_contract();
+        let env = mock_env();
+        let msg = ExecuteMsg::CreateLotto {
+            ticket_price: Coin::new(100, "untrn"),
+            duration_seconds: 90,
+            number_of_winners: 1,
+            community_pool_percentage: 20,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
+        };
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
+        execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+        let alice = mock_info("alice", &[]);
+        let msg = ExecuteMsg::DepositBalance { recipient: None };
+        let info = mock_info("alice", &[Coin::new(250, "untrn")]);
+        execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        let msg = ExecuteMsg::SetCreditWinnings { enabled: true };
+        execute(deps.as_mut(), env.clone(), alice.clone(), msg).unwrap();
+        // tickets bought without funds are paid out of the balance
+        let buy = ExecuteMsg::BuyTicket {
+            lotto_id: 0,
+            entropy: None,
+            vote: None,
+        };
+        for _ in 0..2 {
+            execute(deps.as_mut(), env.clone(), alice.clone(), buy.clone()).unwrap();
+        }
+        let err = execute(deps.as_mut(), env.clone(), alice.clone(), buy).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InsufficientBalance {
+                denom: "untrn".to_string()
+            }
+        );
+
+        let msg = ExecuteMsg::NoisReceive {
+            callback: NoisCallback {
+                job_id: "lotto-0".to_string(),
+                published: env.block.time.plus_seconds(100),
+                randomness: HexBinary::from([7; 32]),
+            },
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PROXY_ADDRESS, &[]),
+            msg,
+        )
+        .unwrap();
+        let msg = ExecuteMsg::Settle {
+            lotto_id: 0,
+            limit: None,
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), msg).unwrap();
+        // only the commissions are sent, the prize is credited
+        assert_eq!(res.messages.len(), 2);
+        let query_balance = |deps: Deps| -> PlayerBalanceResponse {
+            from_binary(
+                &query(
+                    deps,
+                    env.clone(),
+                    QueryMsg::PlayerBalance {
+                        address: "alice".to_string(),
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap()
+        };
+        assert_eq!(
+            query_balance(deps.as_ref()),
+            PlayerBalanceResponse {
+                address: "alice".to_string(),
+                coins: vec![Coin::new(170, "untrn")],
+                credit_winnings: true,
+            }
+        );
+        let msg = QueryMsg::PlayerBalance {
+            address: "ALICE".to_string(),
+        };
+        query(deps.as_ref(), env.clone(), msg).unwrap_err();
+
+        let withdraw = |coins| ExecuteMsg::WithdrawBalance { coins };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            alice.clone(),
+            withdraw(vec![Coin::new(0, "untrn")]),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::NothingToWithdraw);
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            alice.clone(),
+            withdraw(vec![Coin::new(171, "untrn")]),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InsufficientBalance {
+                denom: "untrn".to_string()
+            }
+        );
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            alice,
+            withdraw(vec![Coin::new(60, "untrn"), Coin::new(40, "untrn")]),
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: "alice".to_string(),
+                amount: vec![Coin::new(100, "untrn")],
+            })]
+        );
+        assert_eq!(
+            query_balance(deps.as_ref()).coins,
+            vec![Coin::new(70, "untrn")]
+        );
+
+        // the balance is escrowed next to the protocol commission
+        deps.querier
+            .update_balance(MOCK_CONTRACT_ADDR, vec![Coin::new(80, "untrn")]);
+        let solvency: SolvencyResponse =
+            from_binary(&query(deps.as_ref(), env, QueryMsg::Solvency {}).unwrap()).unwrap();
+        assert!(solvency.is_solvent);
+        assert_eq!(
+            solvency.denoms,
+            vec![DenomSolvency {
+                denom: "untrn".to_string(),
+                balance: Uint128::new(80),
+                escrowed: Uint128::new(80),
+                surplus: Uint128::zero(),
+                shortfall: Uint128::zero(),
+                untracked_payouts: Uint128::zero(),
+            }]
+        );
+    }
+
+    #[test]
+    fn subscriptions() {
+        let mut deps = instantiate_contract();
+        let env = mock_env();
+        for (player, amount) in [("alice", 500), ("carol", 100)] {
+            let info = mock_info(player, &[Coin::new(amount, "untrn")]);
+            let msg = ExecuteMsg::DepositBalance { recipient: None };
+            execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        }
+
+        let subscribe = |criteria, tickets_per_lotto, max_spend| ExecuteMsg::Subscribe {
+            criteria,
+            tickets_per_lotto,
+            max_spend,
+        };
+        let daily = SubscriptionCriteriaMsg {
+            creator: Some(CREATOR.to_string()),
+            denom: None,
+            max_price: Some(Uint128::new(100)),
+            series: Some("daily".to_string()),
+        };
+        let alice = mock_info("alice", &[]);
+        // anyone can name a series
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            alice.clone(),
+            subscribe(
+                SubscriptionCriteriaMsg {
+                    creator: None,
+                    ..daily.clone()
+                },
+                2,
+                Coin::new(300, "untrn"),
+            ),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::SeriesWithoutCreator);
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            alice.clone(),
+            subscribe(daily.clone(), 2, Coin::new(300, "uatom")),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::UnfundedSubscription {
+                denom: "uatom".to_string()
+            }
+        );
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            alice.clone(),
+            subscribe(daily.clone(), 6, Coin::new(300, "untrn")),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvalidTicketsPerLotto { max: 5 });
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            alice.clone(),
+            subscribe(
+                SubscriptionCriteriaMsg {
+                    denom: Some("uatom".to_string()),
+                    ..daily.clone()
+                },
+                2,
+                Coin::new(300, "untrn"),
+            ),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::SubscriptionDenomMismatch);
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            alice,
+            subscribe(daily.clone(), 2, Coin::new(300, "untrn")),
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("carol", &[]),
+            subscribe(daily.clone(), 2, Coin::new(1000, "untrn")),
+        )
+        .unwrap();
+        let info = mock_info("bob", &[Coin::new(100, "untrn")]);
+        let msg = ExecuteMsg::DepositBalance { recipient: None };
+        execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        let other_creator = SubscriptionCriteriaMsg {
+            creator: Some("someone".to_string()),
+            denom: None,
+            max_price: None,
+            series: None,
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("bob", &[]),
+            subscribe(other_creator.clone(), 1, Coin::new(1000, "untrn")),
+        )
+        .unwrap();
+
+        let create_lotto = |deps: DepsMut, price: u128, series: Option<&str>| -> Response {
+            let msg = ExecuteMsg::CreateLotto {
+                ticket_price: Coin::new(price, "untrn"),
+                duration_seconds: 90,
+                number_of_winners: 1,
+                community_pool_percentage: 20,
+                commitment: None,
+                beneficiary: None,
+                vote_weighting: None,
+                series: series.map(str::to_string),
+            };
+            let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
+            execute(deps, env.clone(), info, msg).unwrap()
+        };
+        let participants = |deps: Deps, lotto_nonce| -> Vec<String> {
+            let lotto: LottoResponse =
+                from_binary(&query(deps, env.clone(), QueryMsg::Lotto { lotto_nonce }).unwrap())
+                    .unwrap();
+            lotto.participants
+        };
+        // the subscriptions buy their tickets out of the player balances,
+        // carol's ends as her balance only pays for one ticket
+        let res = create_lotto(deps.as_mut(), 100, Some("daily"));
+        let purchases = res
+            .events
+            .iter()
+            .filter(|event| event.ty == events::TICKET_PURCHASED)
+            .count();
+        assert_eq!(purchases, 3);
+        assert_eq!(
+            participants(deps.as_ref(), 0),
+            vec!["alice", "alice", "carol"]
+        );
+        // another series and a ticket price above the criteria don't match
+        create_lotto(deps.as_mut(), 100, None);
+        create_lotto(deps.as_mut(), 200, Some("daily"));
+        assert!(participants(deps.as_ref(), 1).is_empty());
+        assert!(participants(deps.as_ref(), 2).is_empty());
+        // the spending cap only leaves room for one more ticket, then the subscription ends
+        create_lotto(deps.as_mut(), 100, Some("daily"));
+        assert_eq!(participants(deps.as_ref(), 3), vec!["alice"]);
+
+        let subscriptions: SubscriptionsResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::Subscriptions {
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            subscriptions.subscriptions,
+            vec![SubscriptionResponse {
+                subscriber: "bob".to_string(),
+                criteria: other_creator,
+                tickets_per_lotto: 1,
+                max_spend: Coin::new(1000, "untrn"),
+                spent: Uint128::zero(),
+            }]
+        );
+        let balance: PlayerBalanceResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::PlayerBalance {
+                    address: "alice".to_string(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(balance.coins, vec![Coin::new(200, "untrn")]);
+
+        let bob = mock_info("bob", &[]);
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            bob.clone(),
+            ExecuteMsg::Unsubscribe {},
+        )
+        .unwrap();
+        let err = execute(deps.as_mut(), env, bob, ExecuteMsg::Unsubscribe {}).unwrap_err();
+        assert_eq!(err, ContractError::SubscriptionNotFound);
+    }
+
+    #[test]
+    fn settle_in_batches() {
+        let mut deps = instantiate_contract();
+        let env = mock_env();
+        let msg = ExecuteMsg::CreateLotto {
+            ticket_price: Coin::new(100, "untrn"),
+            duration_seconds: 90,
+            number_of_winners: 3,
+            community_pool_percentage: 20,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
+        };
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
+        execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        for i in 1..=5 {
+            let msg = ExecuteMsg::BuyTicket {
+                lotto_id: 0,
+                entropy: None,
+                vote: None,
+            };
+            let info = mock_info(&format!("participant-{i}"), &[Coin::new(100, "untrn")]);
+            execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        }
+
+        let settle = |limit| ExecuteMsg::Settle {
+            lotto_id: 0,
+            limit: Some(limit),
+        };
+        // nothing to settle before the randomness arrives
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            settle(2),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::RandomnessNotReceived);
+
+        let msg = ExecuteMsg::NoisReceive {
+            callback: NoisCallback {
+                job_id: "lotto-0".to_string(),
+                published: env.block.time.plus_seconds(100),
+                randomness: HexBinary::from([7; 32]),
+            },
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PROXY_ADDRESS, &[]),
+            msg.clone(),
+        )
+        .unwrap();
+        // the randomness can only be delivered once
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PROXY_ADDRESS, &[]),
+            msg,
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::LottoClosed);
+
+        // first batch pays the commissions and two winners
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            settle(2),
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 4);
+        assert_eq!(
+            res.attributes.last().unwrap(),
+            Attribute::new("winners_paid", "2")
+        );
+        let lotto: LottoResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::Lotto { lotto_nonce: 0 },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(lotto.status, LottoStatus::Settling);
+        let winners = lotto.winners.unwrap();
+        assert_eq!(winners.len(), 3);
+
+        // second batch pays the last winner only
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            settle(2),
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: winners[2].clone(),
+                amount: vec![Coin::new(100, "untrn")],
+            })]
+        );
+        let lotto: LottoResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::Lotto { lotto_nonce: 0 },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(lotto.status, LottoStatus::Settled);
+
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            settle(2),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::LottoClosed);
+
+        // statistics add up once all batches are paid
+        let expected = Stats {
+            lottos_created: 1,
+            tickets_sold: 5,
+            volume: vec![Coin::new(500, "untrn")],
+            prizes_paid: vec![Coin::new(300, "untrn")],
+            creator_commissions: vec![Coin::new(75, "untrn")],
+            community_pool_contributions: vec![Coin::new(100, "untrn")],
+            protocol_revenue: vec![Coin::new(25, "untrn")],
+        };
+        let stats: Stats =
+            from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::Stats {}).unwrap()).unwrap();
+        assert_eq!(stats, expected);
+        let stats: Stats = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::CreatorStats {
+                    creator: CREATOR.to_string(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(stats, expected);
+        let stats: Stats = from_binary(
+            &query(
+                deps.as_ref(),
+                env,
+                QueryMsg::CreatorStats {
+                    creator: "creator-2".to_string(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(stats.lottos_created, 0);
+        assert_eq!(stats.volume, vec![]);
+    }
+
+    #[test]
+    fn fewer_tickets_than_winners_share_the_prize() {
+        let mut deps = instantiate_contract();
+        let env = mock_env();
+        let create = |number_of_winners| ExecuteMsg::CreateLotto {
+            ticket_price: Coin::new(100, "untrn"),
+            duration_seconds: 90,
+            number_of_winners,
+            community_pool_percentage: 10,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
+        };
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), create(0)).unwrap_err();
+        assert_eq!(err, ContractError::NoWinners);
+        execute(deps.as_mut(), env.clone(), info, create(3)).unwrap();
+        for participant in ["participant-1", "participant-2"] {
+            let msg = ExecuteMsg::BuyTicket {
+                lotto_id: 0,
+                entropy: None,
+                vote: None,
+            };
+            let info = mock_info(participant, &[Coin::new(100, "untrn")]);
+            execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        }
+        let msg = ExecuteMsg::NoisReceive {
+            callback: NoisCallback {
+                job_id: "lotto-0".to_string(),
+                published: env.block.time.plus_seconds(100),
+                randomness: HexBinary::from([7; 32]),
+            },
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PROXY_ADDRESS, &[]),
+            msg,
+        )
+        .unwrap();
+        let payout: PayoutResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::SimulatePayout { lotto_id: 0 },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(payout.winners, 2);
+        assert_eq!(payout.per_winner, Coin::new(70, "untrn"));
+
+        let msg = ExecuteMsg::Settle {
+            lotto_id: 0,
+            limit: None,
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), msg).unwrap();
+        // both tickets win and the whole prize is paid out
+        let prizes: Vec<_> = res
+            .messages
+            .iter()
+            .filter_map(|msg| match &msg.msg {
+                CosmosMsg::Bank(BankMsg::Send { to_address, amount })
+                    if to_address.starts_with("participant-") =>
+                {
+                    Some(amount.clone())
                 }
+                _ => None,
             })
-        })
-        .collect::<Result<_, _>>()?;
-    Ok(LottosResponse { lottos })
-}
+            .collect();
+        assert_eq!(prizes, vec![vec![Coin::new(70, "untrn")]; 2]);
+        let lotto = LOTTOS.load(deps.as_ref().storage, 0).unwrap();
+        assert_eq!(lotto.status, LottoStatus::Settled);
+        assert_eq!(lotto.winners.unwrap().len(), 2);
+        // only the protocol commission stays in the contract
+        assert_eq!(
+            ESCROW
+                .load(deps.as_ref().storage, "untrn".to_string())
+                .unwrap(),
+            Uint128::new(10)
+        );
+        assert_eq!(
+            PROTOCOL_BALANCES
+                .load(deps.as_ref().storage, "untrn".to_string())
+                .unwrap(),
+            Uint128::new(10)
+        );
 
-fn query_protocol_balances(deps: Deps) -> StdResult<ProtocolBalancesResponse> {
-    let balances = PROTOCOL_BALANCES
-        .range(deps.storage, None, None, Order::Ascending)
-        .map(|balance| Coin {
-            denom: balance.as_ref().unwrap().clone().0,
-            amount: balance.unwrap().1,
-        })
-        .collect();
-    Ok(ProtocolBalancesResponse { balances })
-}
+        let draw: VerifyDrawResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::VerifyDraw { lotto_id: 0 },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert!(draw.is_valid);
+    }
 
-fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
-    let config = CONFIG.load(deps.storage)?;
-    Ok(ConfigResponse {
-        manager: config.manager.to_string(),
-        nois_proxy: config.nois_proxy.to_string(),
-        is_paused: config.is_paused,
-    })
-}
+    #[test]
+    fn settle_without_commissions() {
+        let mut deps = instantiate_contract();
+        let env = mock_env();
+        let msg = ExecuteMsg::SetConfig {
+            nois_proxy: None,
+            manager: None,
+            lotto_nonce: None,
+            community_pool: None,
+            protocol_commission_percent: None,
+            creator_commission_percent: Some(0),
+            is_paused: None,
+            randomness_source: None,
+            max_duration_seconds: None,
+            allowed_denoms: None,
+            community_pool_mode: None,
+            swap_router: None,
+            reserve_pays_nois_fee: None,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(MANAGER, &[]), msg).unwrap();
+        let msg = ExecuteMsg::CreateLotto {
+            ticket_price: Coin::new(100, "untrn"),
+            duration_seconds: 90,
+            number_of_winners: 1,
+            community_pool_percentage: 0,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
+        };
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
+        execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        for participant in ["participant-1", "participant-2"] {
+            let msg = ExecuteMsg::BuyTicket {
+                lotto_id: 0,
+                entropy: None,
+                vote: None,
+            };
+            let info = mock_info(participant, &[Coin::new(100, "untrn")]);
+            execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        }
+        let msg = ExecuteMsg::NoisReceive {
+            callback: NoisCallback {
+                job_id: "lotto-0".to_string(),
+                published: env.block.time.plus_seconds(100),
+                randomness: HexBinary::from([7; 32]),
+            },
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PROXY_ADDRESS, &[]),
+            msg,
+        )
+        .unwrap();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use cosmwasm_std::testing::{
-        mock_dependencies, mock_env, mock_info, MockApi, MockQuerier, MockStorage,
-    };
-    use cosmwasm_std::{from_binary, Empty, HexBinary, OwnedDeps, SubMsg, Timestamp};
+        let msg = ExecuteMsg::Settle {
+            lotto_id: 0,
+            limit: None,
+        };
+        let res = execute(deps.as_mut(), env, mock_info("anyone", &[]), msg).unwrap();
+        // neither the creator nor the community pool get a transfer of zero coins,
+        // the winner gets everything but the protocol commission
+        let lotto = LOTTOS.load(deps.as_ref().storage, 0).unwrap();
+        assert_eq!(lotto.status, LottoStatus::Settled);
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: lotto.winners.unwrap()[0].to_string(),
+                amount: vec![Coin::new(190, "untrn")],
+            })]
+        );
+    }
 
-    const CREATOR: &str = "creator1";
-    const PROXY_ADDRESS: &str = "the proxy of choice";
-    const MANAGER: &str = "manager";
-    const COM_POOL: &str = "community_pool";
+    #[test]
+    fn address_history_queries() {
+        let mut deps = instantiate_contract();
+        let env = mock_env();
+        for _ in 0..2 {
+            let msg = ExecuteMsg::CreateLotto {
+                ticket_price: Coin::new(100, "untrn"),
+                duration_seconds: 90,
+                number_of_winners: 1,
+                community_pool_percentage: 20,
+                commitment: None,
+                beneficiary: None,
+                vote_weighting: None,
+                series: None,
+            };
+            let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
+            execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        }
+        for lotto_id in [0, 0, 1] {
+            let msg = ExecuteMsg::BuyTicket {
+                lotto_id,
+                entropy: None,
+                vote: None,
+            };
+            let info = mock_info("participant-1", &[Coin::new(100, "untrn")]);
+            execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        }
 
-    fn instantiate_contract() -> OwnedDeps<MockStorage, MockApi, MockQuerier, Empty> {
-        let mut deps = mock_dependencies();
-        let msg = InstantiateMsg {
-            manager: MANAGER.to_string(),
-            nois_proxy: PROXY_ADDRESS.to_string(),
-            community_pool: COM_POOL.to_string(),
-            protocol_commission_percent: 5,
-            creator_commission_percent: 15,
+        let msg = ExecuteMsg::NoisReceive {
+            callback: NoisCallback {
+                job_id: "lotto-0".to_string(),
+                published: env.block.time.plus_seconds(100),
+                randomness: HexBinary::from([7; 32]),
+            },
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PROXY_ADDRESS, &[]),
+            msg,
+        )
+        .unwrap();
+        let msg = ExecuteMsg::Settle {
+            lotto_id: 0,
+            limit: None,
         };
+        execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), msg).unwrap();
 
-        let info = mock_info(CREATOR, &[]);
-        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
-        deps
+        let AddressLottosResponse { lottos } = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::TicketsByAddress {
+                    address: "participant-1".to_string(),
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            lottos,
+            vec![
+                AddressLotto {
+                    lotto_id: 1,
+                    tickets: 1,
+                    amount_won: Uint128::zero(),
+                    denom: "untrn".to_string(),
+                },
+                AddressLotto {
+                    lotto_id: 0,
+                    tickets: 2,
+                    amount_won: Uint128::new(120),
+                    denom: "untrn".to_string(),
+                },
+            ]
+        );
+        // pagination
+        let AddressLottosResponse { lottos } = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::TicketsByAddress {
+                    address: "participant-1".to_string(),
+                    start_after: Some(1),
+                    limit: Some(1),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(lottos.len(), 1);
+        assert_eq!(lottos[0].lotto_id, 0);
+
+        let AddressLottosResponse { lottos } = from_binary(
+            &query(
+                deps.as_ref(),
+                env,
+                QueryMsg::WinningsByAddress {
+                    address: "participant-1".to_string(),
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            lottos,
+            vec![AddressLotto {
+                lotto_id: 0,
+                tickets: 2,
+                amount_won: Uint128::new(120),
+                denom: "untrn".to_string(),
+            }]
+        );
     }
 
     #[test]
-    fn proper_instantiation() {
-        let deps = instantiate_contract();
+    fn odds_query_works() {
+        let mut deps = instantiate_contract();
         let env = mock_env();
+        let msg = ExecuteMsg::CreateLotto {
+            ticket_price: Coin::new(100, "untrn"),
+            duration_seconds: 90,
+            number_of_winners: 2,
+            community_pool_percentage: 20,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
+        };
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
+        execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        for participant in [
+            "participant-1",
+            "participant-1",
+            "participant-2",
+            "participant-3",
+        ] {
+            let msg = ExecuteMsg::BuyTicket {
+                lotto_id: 0,
+                entropy: None,
+                vote: None,
+            };
+            let info = mock_info(participant, &[Coin::new(100, "untrn")]);
+            execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        }
 
-        // it worked, let's query the state
-        let res = query(deps.as_ref(), env, QueryMsg::Config {}).unwrap();
-        let config: ConfigResponse = from_binary(&res).unwrap();
-        assert_eq!(MANAGER, config.manager.as_str());
+        let odds = |address: &str| -> OddsResponse {
+            from_binary(
+                &query(
+                    deps.as_ref(),
+                    env.clone(),
+                    QueryMsg::Odds {
+                        lotto_id: 0,
+                        address: address.to_string(),
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap()
+        };
+        // Losing means both winning tickets are among the 2 others: 1 pair out of 6
+        let res = odds("participant-1");
+        assert_eq!(res.tickets, 2);
+        assert_eq!(res.total_tickets, 4);
+        assert_eq!(res.probability, Decimal::from_ratio(5u128, 6u128));
+        // Losing means both winning tickets are among the 3 others: 3 pairs out of 6
+        assert_eq!(odds("participant-2").probability, Decimal::percent(50));
+        let res = odds("outsider");
+        assert_eq!(res.tickets, 0);
+        assert_eq!(res.probability, Decimal::zero());
+
+        assert_eq!(win_probability(4, 1, 10), Decimal::one());
+        assert_eq!(win_probability(3, 2, 2), Decimal::one());
+        assert_eq!(win_probability(0, 0, 1), Decimal::zero());
+        // 1 - (90 * 89 * 88) / (100 * 99 * 98)
+        assert_eq!(
+            win_probability(100, 10, 3),
+            Decimal::from_ratio(970200u128 - 704880, 970200u128)
+        );
+        // Large lottos stay within bounds
+        assert!(win_probability(1_000_000, 1_000, 500_000) > Decimal::percent(99));
     }
 
     #[test]
@@ -665,7 +6721,7 @@ mod tests {
         let env = mock_env();
         // Create few lottos
         // lotto-0
-        let info = mock_info(CREATOR, &[]);
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
         let msg = ExecuteMsg::CreateLotto {
             ticket_price: Coin {
                 denom: "untrn".to_string(),
@@ -674,10 +6730,14 @@ mod tests {
             duration_seconds: 90,
             number_of_winners: 2,
             community_pool_percentage: 20,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
         };
         execute(deps.as_mut(), env.clone(), info, msg).unwrap();
         // lotto-1
-        let info = mock_info(CREATOR, &[]);
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
         let msg = ExecuteMsg::CreateLotto {
             ticket_price: Coin {
                 denom: "untrn".to_string(),
@@ -686,10 +6746,14 @@ mod tests {
             duration_seconds: 90,
             number_of_winners: 2,
             community_pool_percentage: 20,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
         };
         execute(deps.as_mut(), env.clone(), info, msg).unwrap();
         // lotto-2
-        let info = mock_info(CREATOR, &[]);
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
         let msg = ExecuteMsg::CreateLotto {
             ticket_price: Coin {
                 denom: "untrn".to_string(),
@@ -698,11 +6762,15 @@ mod tests {
             duration_seconds: 90,
             number_of_winners: 2,
             community_pool_percentage: 20,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
         };
         execute(deps.as_mut(), env.clone(), info, msg).unwrap();
 
         // lotto-3
-        let info = mock_info("creator-2", &[]);
+        let info = mock_info("creator-2", &[Coin::new(NOIS_PRICE, "unois")]);
         let msg = ExecuteMsg::CreateLotto {
             ticket_price: Coin {
                 denom: "untrn".to_string(),
@@ -711,10 +6779,14 @@ mod tests {
             duration_seconds: 90,
             number_of_winners: 2,
             community_pool_percentage: 20,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
         };
         execute(deps.as_mut(), env.clone(), info, msg).unwrap();
         // lotto-4
-        let info = mock_info("creator-2", &[]);
+        let info = mock_info("creator-2", &[Coin::new(NOIS_PRICE, "unois")]);
         let msg = ExecuteMsg::CreateLotto {
             ticket_price: Coin {
                 denom: "untrn".to_string(),
@@ -723,16 +6795,22 @@ mod tests {
             duration_seconds: 90,
             number_of_winners: 2,
             community_pool_percentage: 20,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
         };
         execute(deps.as_mut(), env.clone(), info, msg).unwrap();
 
         // Query CREATOR ASC
-        let LottosResponse { lottos } = from_binary(
+        let LottosResponse { lottos, .. } = from_binary(
             &query(
                 deps.as_ref(),
                 mock_env(),
                 QueryMsg::LottosAsc {
                     creator: Some(CREATOR.to_string()),
+                    denom: None,
+                    status: None,
                     is_active: Some(true),
                     start_after: None,
                     limit: Some(10),
@@ -744,12 +6822,14 @@ mod tests {
         let response_lotto_nonces = lottos.iter().map(|b| b.nonce).collect::<Vec<u64>>();
         assert_eq!(response_lotto_nonces, [0, 1, 2]);
         // Query creator-2 desc
-        let LottosResponse { lottos } = from_binary(
+        let LottosResponse { lottos, .. } = from_binary(
             &query(
                 deps.as_ref(),
                 mock_env(),
                 QueryMsg::LottosDesc {
                     creator: Some("creator-2".to_string()),
+                    denom: None,
+                    status: None,
                     is_active: Some(true),
                     start_after: None,
                     limit: Some(10),
@@ -761,12 +6841,14 @@ mod tests {
         let response_lotto_nonces = lottos.iter().map(|b| b.nonce).collect::<Vec<u64>>();
         assert_eq!(response_lotto_nonces, [4, 3]);
         // Query all creators desc
-        let LottosResponse { lottos } = from_binary(
+        let LottosResponse { lottos, .. } = from_binary(
             &query(
                 deps.as_ref(),
                 mock_env(),
                 QueryMsg::LottosDesc {
                     creator: None,
+                    denom: None,
+                    status: None,
                     is_active: Some(true),
                     start_after: None,
                     limit: Some(10),
@@ -778,12 +6860,17 @@ mod tests {
         let response_lotto_nonces = lottos.iter().map(|b| b.nonce).collect::<Vec<u64>>();
         assert_eq!(response_lotto_nonces, [4, 3, 2, 1, 0]);
         // Query all creators desc with limit 2
-        let LottosResponse { lottos } = from_binary(
+        let LottosResponse {
+            lottos,
+            next_start_after,
+        } = from_binary(
             &query(
                 deps.as_ref(),
                 mock_env(),
                 QueryMsg::LottosDesc {
                     creator: None,
+                    denom: None,
+                    status: None,
                     is_active: Some(true),
                     start_after: None,
                     limit: Some(2),
@@ -794,13 +6881,39 @@ mod tests {
         .unwrap();
         let response_lotto_nonces = lottos.iter().map(|b| b.nonce).collect::<Vec<u64>>();
         assert_eq!(response_lotto_nonces, [4, 3]);
+        assert_eq!(next_start_after, Some(3));
+        // and the next page
+        let LottosResponse {
+            lottos,
+            next_start_after,
+        } = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::LottosDesc {
+                    creator: None,
+                    denom: None,
+                    status: None,
+                    is_active: Some(true),
+                    start_after: next_start_after,
+                    limit: Some(2),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let response_lotto_nonces = lottos.iter().map(|b| b.nonce).collect::<Vec<u64>>();
+        assert_eq!(response_lotto_nonces, [2, 1]);
+        assert_eq!(next_start_after, Some(1));
         // Query all inactive lottos
-        let LottosResponse { lottos } = from_binary(
+        let LottosResponse { lottos, .. } = from_binary(
             &query(
                 deps.as_ref(),
                 mock_env(),
                 QueryMsg::LottosDesc {
                     creator: None,
+                    denom: None,
+                    status: None,
                     is_active: Some(false),
                     start_after: None,
                     limit: Some(2),
@@ -811,14 +6924,220 @@ mod tests {
         .unwrap();
         let response_lotto_nonces = lottos.iter().map(|b| b.nonce).collect::<Vec<u64>>();
         assert_eq!(response_lotto_nonces, [] as [u64; 0]);
+
+        // lotto-5 in another denom, settled through commit-reveal
+        let secret = HexBinary::from(b"creator secret");
+        let msg = ExecuteMsg::CreateLotto {
+            ticket_price: Coin::new(100, "uatom"),
+            duration_seconds: 90,
+            number_of_winners: 1,
+            community_pool_percentage: 20,
+            commitment: Some(HexBinary::from(Sha256::digest(secret.as_slice()).to_vec())),
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
+        };
+        let info = mock_info("creator-2", &[Coin::new(NOIS_PRICE, "unois")]);
+        execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        let msg = ExecuteMsg::BuyTicket {
+            lotto_id: 5,
+            entropy: None,
+            vote: None,
+        };
+        let info = mock_info("participant-1", &[Coin::new(100, "uatom")]);
+        execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        let mut later = env.clone();
+        later.block.time = later.block.time.plus_seconds(91);
+        let msg = ExecuteMsg::Reveal {
+            lotto_id: 5,
+            secret,
+        };
+        execute(
+            deps.as_mut(),
+            later.clone(),
+            mock_info("creator-2", &[]),
+            msg,
+        )
+        .unwrap();
+        let msg = ExecuteMsg::NoisReceive {
+            callback: NoisCallback {
+                job_id: "lotto-5".to_string(),
+                published: later.block.time.plus_seconds(REVEAL_PERIOD_SECONDS),
+                randomness: HexBinary::from([7; 32]),
+            },
+        };
+        execute(
+            deps.as_mut(),
+            later.clone(),
+            mock_info(PROXY_ADDRESS, &[]),
+            msg,
+        )
+        .unwrap();
+
+        let query_nonces = |denom: Option<&str>, status: Option<LottoStatus>| {
+            let LottosResponse { lottos, .. } = from_binary(
+                &query(
+                    deps.as_ref(),
+                    mock_env(),
+                    QueryMsg::LottosAsc {
+                        creator: None,
+                        denom: denom.map(str::to_string),
+                        status,
+                        is_active: None,
+                        start_after: None,
+                        limit: Some(10),
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap();
+            lottos.iter().map(|b| b.nonce).collect::<Vec<u64>>()
+        };
+        // Query by denom
+        assert_eq!(query_nonces(Some("uatom"), None), [5]);
+        assert_eq!(query_nonces(Some("untrn"), None), [0, 1, 2, 3, 4]);
+        // Query by status
+        assert_eq!(query_nonces(None, Some(LottoStatus::Settling)), [5]);
+        assert_eq!(query_nonces(None, Some(LottoStatus::Open)), [0, 1, 2, 3, 4]);
+        assert_eq!(
+            query_nonces(Some("untrn"), Some(LottoStatus::Settling)),
+            [] as [u64; 0]
+        );
+    }
+    #[test]
+    fn solvency_query_works() {
+        let mut deps = lotto_with_five_participants();
+        settle_first_lotto(deps.as_mut());
+        let solvency = |deps: Deps| -> SolvencyResponse {
+            from_binary(&query(deps, mock_env(), QueryMsg::Solvency {}).unwrap()).unwrap()
+        };
+
+        // only the protocol balance is still owed, but the mocked bank balance is empty
+        assert_eq!(
+            solvency(deps.as_ref()),
+            SolvencyResponse {
+                is_solvent: false,
+                denoms: vec![DenomSolvency {
+                    denom: "untrn".to_string(),
+                    balance: Uint128::zero(),
+                    escrowed: Uint128::new(25000000),
+                    surplus: Uint128::zero(),
+                    shortfall: Uint128::new(25000000),
+                    untracked_payouts: Uint128::zero(),
+                }],
+            }
+        );
+        let msg = ExecuteMsg::WithdrawAll {
+            address: "manager_second_address".to_string(),
+            denom: "untrn".to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(MANAGER, &[]), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::EscrowedFunds {
+                denom: "untrn".to_string()
+            }
+        );
+
+        deps.querier
+            .update_balance(MOCK_CONTRACT_ADDR, vec![Coin::new(25000003, "untrn")]);
+        let solvency = solvency(deps.as_ref());
+        assert!(solvency.is_solvent);
+        assert_eq!(solvency.denoms[0].surplus, Uint128::new(3));
+    }
+
+    #[test]
+    fn simulate_payout_works() {
+        let deps = lotto_with_five_participants();
+        // The split is known before the lotto settles
+        let payout: PayoutResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::SimulatePayout { lotto_id: 0 },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            payout,
+            PayoutResponse {
+                lotto_id: 0,
+                balance: Coin::new(500_000000, "untrn"),
+                community_pool: Coin::new(100_000000, "untrn"),
+                creator: Coin::new(75_000000, "untrn"),
+                protocol: Coin::new(25_000000, "untrn"),
+                per_winner: Coin::new(150_000000, "untrn"),
+                winners: 2,
+            }
+        );
+        let err = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::SimulatePayout { lotto_id: 1 },
+        )
+        .unwrap_err();
+        assert!(matches!(err, StdError::NotFound { .. }));
+    }
+
+    #[test]
+    fn verify_draw_works() {
+        let mut deps = lotto_with_five_participants();
+        let verify = |deps: Deps| -> VerifyDrawResponse {
+            from_binary(&query(deps, mock_env(), QueryMsg::VerifyDraw { lotto_id: 0 }).unwrap())
+                .unwrap()
+        };
+        settle_first_lotto(deps.as_mut());
+
+        // Anyone can verify the draw from the stored proof
+        let draw = verify(deps.as_ref());
+        assert!(draw.is_valid);
+        assert_eq!(draw.winners, ["participant-4", "participant-5"]);
+        assert_eq!(draw.randomness, draw.beacon);
+        assert_eq!(draw.secret, None);
+        assert_eq!(draw.published, Timestamp::from_seconds(1682086395));
+        assert_eq!(
+            draw.participants,
+            [
+                "participant-1",
+                "participant-2",
+                "participant-3",
+                "participant-4",
+                "participant-5"
+            ]
+        );
+
+        // Tampered winners don't match the draw
+        LOTTOS
+            .update(deps.as_mut().storage, 0, |lotto| -> StdResult<_> {
+                let mut lotto = lotto.unwrap();
+                lotto.winners = Some(vec![Addr::unchecked("participant-1")]);
+                Ok(lotto)
+            })
+            .unwrap();
+        assert!(!verify(deps.as_ref()).is_valid);
+        // and so does a tampered participant list
+        LOTTOS
+            .update(deps.as_mut().storage, 0, |lotto| -> StdResult<_> {
+                let mut lotto = lotto.unwrap();
+                lotto.winners = Some(vec![
+                    Addr::unchecked("participant-4"),
+                    Addr::unchecked("participant-5"),
+                ]);
+                lotto.participants.swap(0, 1);
+                Ok(lotto)
+            })
+            .unwrap();
+        assert!(!verify(deps.as_ref()).is_valid);
     }
+
     #[test]
     fn lotto_works() {
         let mut deps = instantiate_contract();
         let env = mock_env();
 
         // creator creates a lotto instance
-        let info = mock_info(CREATOR, &[]);
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
         let msg = ExecuteMsg::CreateLotto {
             ticket_price: Coin {
                 denom: "untrn".to_string(),
@@ -827,6 +7146,10 @@ mod tests {
             duration_seconds: 90,
             number_of_winners: 2,
             community_pool_percentage: 20,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
         };
         execute(deps.as_mut(), env.clone(), info, msg).unwrap();
 
@@ -840,11 +7163,17 @@ mod tests {
             protocol_commission_percent: None,
             creator_commission_percent: None,
             is_paused: Some(true),
+            randomness_source: None,
+            max_duration_seconds: None,
+            allowed_denoms: None,
+            community_pool_mode: None,
+            swap_router: None,
+            reserve_pays_nois_fee: None,
         };
         execute(deps.as_mut(), env.clone(), info, msg).unwrap();
 
         // creator creates a second lotto instance after the contract was paused
-        let info = mock_info(CREATOR, &[]);
+        let info = mock_info(CREATOR, &[Coin::new(NOIS_PRICE, "unois")]);
         let msg = ExecuteMsg::CreateLotto {
             ticket_price: Coin {
                 denom: "untrn".to_string(),
@@ -853,6 +7182,10 @@ mod tests {
             duration_seconds: 90,
             number_of_winners: 2,
             community_pool_percentage: 20,
+            commitment: None,
+            beneficiary: None,
+            vote_weighting: None,
+            series: None,
         };
         let err = execute(deps.as_mut(), env.clone(), info, msg).unwrap_err();
         assert_eq!(err, ContractError::ContractIsPaused);
@@ -862,7 +7195,11 @@ mod tests {
             "participant-1",
             &[Coin::new(50_000_000, "untrn".to_string())],
         );
-        let msg = ExecuteMsg::BuyTicket { lotto_id: 0 };
+        let msg = ExecuteMsg::BuyTicket {
+            lotto_id: 0,
+            entropy: None,
+            vote: None,
+        };
         let err = execute(deps.as_mut(), env.clone(), info, msg).unwrap_err();
         assert_eq!(err, ContractError::InvalidPayment {});
         // someone deposits for inexistant lotto
@@ -870,12 +7207,20 @@ mod tests {
             "participant-1",
             &[Coin::new(50_000_000, "untrn".to_string())],
         );
-        let msg = ExecuteMsg::BuyTicket { lotto_id: 1 };
+        let msg = ExecuteMsg::BuyTicket {
+            lotto_id: 1,
+            entropy: None,
+            vote: None,
+        };
         let err = execute(deps.as_mut(), env.clone(), info, msg).unwrap_err();
         assert_eq!(err, ContractError::LottoNotFound {});
 
         // someone deposits correctly
-        let msg = ExecuteMsg::BuyTicket { lotto_id: 0 };
+        let msg = ExecuteMsg::BuyTicket {
+            lotto_id: 0,
+            entropy: None,
+            vote: None,
+        };
         let info = mock_info(
             "participant-1",
             &[Coin::new(100_000_000, "untrn".to_string())],
@@ -918,9 +7263,25 @@ mod tests {
         assert_eq!(
             res.attributes,
             vec![
-                Attribute::new("action", "receive-randomness-and-send-prize"),
+                Attribute::new("action", "receive-randomness"),
                 Attribute::new("job_id", "lotto-0"),
+            ]
+        );
+        assert_eq!(res.messages, vec![]);
+
+        // Anyone can settle the lotto
+        let msg = ExecuteMsg::Settle {
+            lotto_id: 0,
+            limit: None,
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), msg).unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                Attribute::new("action", "settle"),
+                Attribute::new("lotto_id", "0"),
                 Attribute::new("winner_send_amount", "150000000untrn"),
+                Attribute::new("winners_paid", "2"),
             ]
         );
         let expected = vec![
@@ -985,6 +7346,10 @@ mod tests {
             }
         );
 
+        // withdrawals need the funds to be in the contract
+        deps.querier
+            .update_balance(MOCK_CONTRACT_ADDR, vec![Coin::new(25000000, "untrn")]);
+
         // manager tries to withdraw smart contract funds
         let info = mock_info(MANAGER, &[]);
         let msg = ExecuteMsg::WithdrawAll {
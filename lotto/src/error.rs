@@ -1,4 +1,4 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{Coin, StdError};
 use thiserror::Error;
 
 #[derive(Error, Debug, PartialEq)]
@@ -28,6 +28,12 @@ pub enum ContractError {
     #[error("Invalid Randomness")]
     InvalidRandomness,
 
+    #[error("Invalid Job Id: {job_id:?}")]
+    InvalidJobId { job_id: String },
+
+    #[error("Randomness was published before the lotto expired")]
+    RandomnessTooEarly,
+
     #[error("No Depositors")]
     NoDepositors,
 
@@ -40,9 +46,144 @@ pub enum ContractError {
     #[error("The protocol does not own anything in {denom:?} ")]
     ProtocolBalanceDoesNotOwnSuchDenom { denom: String },
 
+    #[error("Commitment must be a 32 bytes sha256 hash")]
+    InvalidCommitment,
+
+    #[error("Revealed secret does not match the commitment")]
+    InvalidReveal,
+
+    #[error("Lotto does not use commit-reveal randomness")]
+    NotCommitReveal,
+
+    #[error("Randomness has not been received yet")]
+    RandomnessNotReceived,
+
+    #[error("Lotto is still running")]
+    LottoNotExpired,
+
+    #[error("Reveal deadline has passed")]
+    RevealDeadlinePassed,
+
+    #[error("Reveal deadline has not passed yet")]
+    RevealDeadlineNotPassed,
+
+    #[error("The secret was revealed, the lotto is drawn once the beacon arrives")]
+    SecretRevealed,
+
+    #[error("Lotto is already settled or refunded")]
+    LottoClosed,
+
+    #[error("Lotto duration exceeds the maximum of {max_duration_seconds} seconds")]
+    DurationTooLong { max_duration_seconds: u64 },
+
+    #[error("Tickets cannot be priced in {denom:?}")]
+    DenomNotAllowed { denom: String },
+
+    #[error("Funding the community pool module requires the distribution feature")]
+    DistributionFeatureDisabled,
+
+    #[error("{address:?} is not a registered beneficiary")]
+    BeneficiaryNotRegistered { address: String },
+
+    #[error("Lotto does not accept votes")]
+    VotingDisabled,
+
+    #[error("Revenue recipients need a positive weight")]
+    InvalidRevenueWeight,
+
+    #[error("No revenue recipients configured")]
+    NoRevenueRecipients,
+
+    #[error("Nothing to distribute")]
+    NothingToDistribute,
+
+    #[error("No swap router configured")]
+    NoSwapRouter,
+
+    #[error("Cannot convert {denom:?} into itself")]
+    InvalidConversion { denom: String },
+
+    #[error("Swap returned less than {min_out}")]
+    SwapOutputTooLow { min_out: Coin },
+
+    #[error("{contract:?} is not a registered hook")]
+    HookNotRegistered { contract: String },
+
+    #[error("The sender is not the IBC-hooks account of the original sender on this channel")]
+    InvalidIbcHooksSender,
+
+    #[error("The original sender {original_sender:?} is an address of this chain")]
+    LocalOriginalSender { original_sender: String },
+
+    #[error("Tickets of this lotto were already bought over IBC with another payout")]
+    CrossChainPayoutMismatch,
+
+    #[error("Invalid payout channel {channel:?}, expected a channel id and a remote address")]
+    InvalidPayoutChannel { channel: String },
+
+    #[error("No payout channel is registered")]
+    NoPayoutChannel,
+
+    #[error("Nothing to claim")]
+    NothingToClaim,
+
+    #[error("Reply to an ICS20 payout that wasn't sent")]
+    UnexpectedIbcPayoutReply,
+
+    #[error("Invalid signature")]
+    InvalidSignature,
+
+    #[error("The authorization expired")]
+    AuthorizationExpired,
+
+    #[error("Invalid nonce, expected {expected}")]
+    InvalidNonce { expected: u64 },
+
+    #[error("The balance in {denom:?} is too low")]
+    InsufficientBalance { denom: String },
+
+    #[error("A subscription buys between 1 and {max} tickets per lotto")]
+    InvalidTicketsPerLotto { max: u32 },
+
+    #[error("The denom criterion has to be the denom of the spending cap")]
+    SubscriptionDenomMismatch,
+
+    #[error("The spending cap can't be zero")]
+    ZeroSpendingCap,
+
+    #[error("Subscribing needs a player balance in {denom:?}")]
+    UnfundedSubscription { denom: String },
+
+    #[error("The series criterion needs the creator criterion")]
+    SeriesWithoutCreator,
+
+    #[error("No subscription found")]
+    SubscriptionNotFound,
+
+    #[error("Unknown reply id {id}")]
+    UnknownReplyId { id: u64 },
+
+    #[error("Nothing to withdraw")]
+    NothingToWithdraw,
+
+    #[error("Withdrawal would use funds escrowed in {denom:?}")]
+    EscrowedFunds { denom: String },
+
     #[error("Contract is paused")]
     ContractIsPaused,
 
     #[error("Incorrect Rates")]
     IncorrectRates,
+
+    #[error("A lotto needs at least one winner")]
+    NoWinners,
+
+    #[error("The Nois proxy does not accept payments in {denom:?}")]
+    UnsupportedNoisFeeDenom { denom: String },
+
+    #[error("Insufficient Nois fee, {required} is required")]
+    InsufficientNoisFee { required: Coin },
+
+    #[error("The protocol balance in {denom:?} is too low")]
+    InsufficientProtocolBalance { denom: String },
 }
@@ -0,0 +1,167 @@
+//! Typed events that indexers can rely on. The chain prefixes the type of every
+//! custom event with `wasm-`, so `lotto_created` is indexed as `wasm-lotto_created`.
+//! Amounts are formatted as coins (`100untrn`), lists are comma separated and
+//! timestamps are in nanoseconds.
+
+use cosmwasm_std::{Addr, Coin, Event, Uint128};
+
+use crate::state::{CommunityPoolMode, Config, Lotto, RandomnessSource, Withdrawal};
+
+pub const LOTTO_CREATED: &str = "lotto_created";
+pub const TICKET_PURCHASED: &str = "ticket_purchased";
+pub const LOTTO_SETTLED: &str = "lotto_settled";
+pub const CONFIG_UPDATED: &str = "config_updated";
+pub const WITHDRAWAL: &str = "withdrawal";
+
+/// `wasm-lotto_created` with the attributes
+/// - `lotto_id`
+/// - `creator`
+/// - `ticket_price`
+/// - `expiration`
+/// - `number_of_winners`
+/// - `community_pool_percentage`
+/// - `nois_fee`: what the randomness costs, paid by the creator or the Nois fee reserve
+pub fn lotto_created(lotto: &Lotto, nois_fee: &Coin) -> Event {
+    Event::new(LOTTO_CREATED)
+        .add_attribute("lotto_id", lotto.nonce.to_string())
+        .add_attribute("creator", &lotto.creator)
+        .add_attribute("ticket_price", lotto.ticket_price.to_string())
+        .add_attribute("expiration", lotto.expiration.nanos().to_string())
+        .add_attribute("number_of_winners", lotto.number_of_winners.to_string())
+        .add_attribute(
+            "community_pool_percentage",
+            lotto.community_pool_percentage.to_string(),
+        )
+        .add_attribute("nois_fee", nois_fee.to_string())
+}
+
+/// `wasm-ticket_purchased` with the attributes
+/// - `lotto_id`
+/// - `buyer`
+/// - `ticket_price`
+/// - `tickets_sold`: tickets of the lotto including this one
+/// - `balance`: the lotto balance including this ticket
+pub fn ticket_purchased(lotto: &Lotto, buyer: &Addr) -> Event {
+    Event::new(TICKET_PURCHASED)
+        .add_attribute("lotto_id", lotto.nonce.to_string())
+        .add_attribute("buyer", buyer)
+        .add_attribute("ticket_price", lotto.ticket_price.to_string())
+        .add_attribute("tickets_sold", lotto.participants.len().to_string())
+        .add_attribute(
+            "balance",
+            Coin::new(lotto.balance.u128(), &lotto.ticket_price.denom).to_string(),
+        )
+}
+
+/// Commissions paid when a lotto settles, all in the ticket price denom
+pub struct Commissions {
+    pub creator: Uint128,
+    pub protocol: Uint128,
+    pub community_pool: Uint128,
+}
+
+/// `wasm-lotto_settled`, emitted once every winner is paid, with the attributes
+/// - `lotto_id`
+/// - `winners`: one entry per prize, an address winning twice is listed twice. Left out
+///   when nobody bought a ticket, since the chain rejects empty attribute values.
+/// - `prize_per_winner`
+/// - `creator_commission`
+/// - `protocol_commission`
+/// - `community_pool_share`
+pub fn lotto_settled(lotto: &Lotto, prize_per_winner: Uint128, commissions: &Commissions) -> Event {
+    let denom = &lotto.ticket_price.denom;
+    let winners = lotto
+        .winners
+        .iter()
+        .flatten()
+        .map(Addr::as_str)
+        .collect::<Vec<_>>()
+        .join(",");
+    let mut event = Event::new(LOTTO_SETTLED).add_attribute("lotto_id", lotto.nonce.to_string());
+    if !winners.is_empty() {
+        event = event.add_attribute("winners", winners);
+    }
+    event
+        .add_attribute(
+            "prize_per_winner",
+            Coin::new(prize_per_winner.u128(), denom).to_string(),
+        )
+        .add_attribute(
+            "creator_commission",
+            Coin::new(commissions.creator.u128(), denom).to_string(),
+        )
+        .add_attribute(
+            "protocol_commission",
+            Coin::new(commissions.protocol.u128(), denom).to_string(),
+        )
+        .add_attribute(
+            "community_pool_share",
+            Coin::new(commissions.community_pool.u128(), denom).to_string(),
+        )
+}
+
+/// `wasm-config_updated` with every config field after the update.
+/// The chain rejects empty attribute values, so `swap_router` is left out when unset and
+/// `allowed_denoms` when any denom is accepted.
+pub fn config_updated(config: &Config) -> Event {
+    let randomness_source = match config.randomness_source {
+        RandomnessSource::Nois => "nois",
+        #[cfg(any(test, feature = "mock"))]
+        RandomnessSource::Mock => "mock",
+    };
+    let community_pool_mode = match config.community_pool_mode {
+        CommunityPoolMode::Address => "address",
+        CommunityPoolMode::Distribution => "distribution",
+    };
+    let mut event = Event::new(CONFIG_UPDATED)
+        .add_attribute("manager", &config.manager)
+        .add_attribute("nois_proxy", &config.nois_proxy)
+        .add_attribute("lotto_nonce", config.lotto_nonce.to_string())
+        .add_attribute("community_pool", &config.community_pool)
+        .add_attribute(
+            "protocol_commission_percent",
+            config.protocol_commission_percent.to_string(),
+        )
+        .add_attribute(
+            "creator_commission_percent",
+            config.creator_commission_percent.to_string(),
+        )
+        .add_attribute("is_paused", config.is_paused.to_string())
+        .add_attribute("randomness_source", randomness_source)
+        .add_attribute(
+            "max_duration_seconds",
+            config.max_duration_seconds.to_string(),
+        );
+    if !config.allowed_denoms.is_empty() {
+        event = event.add_attribute("allowed_denoms", config.allowed_denoms.join(","));
+    }
+    event = event.add_attribute("community_pool_mode", community_pool_mode);
+    if let Some(swap_router) = &config.swap_router {
+        event = event.add_attribute("swap_router", swap_router);
+    }
+    event.add_attribute(
+        "reserve_pays_nois_fee",
+        config.reserve_pays_nois_fee.to_string(),
+    )
+}
+
+/// `wasm-withdrawal` for every entry of the withdrawal audit log, with the attributes
+/// - `id`: the id in the audit log
+/// - `initiator`: the manager for withdrawals, anyone for revenue distributions
+/// - `recipient`
+/// - `amount`
+pub fn withdrawal(id: u64, withdrawal: &Withdrawal) -> Event {
+    Event::new(WITHDRAWAL)
+        .add_attribute("id", id.to_string())
+        .add_attribute("initiator", &withdrawal.initiator)
+        .add_attribute("recipient", &withdrawal.recipient)
+        .add_attribute("amount", coins_string(&withdrawal.coins))
+}
+
+pub fn coins_string(coins: &[Coin]) -> String {
+    coins
+        .iter()
+        .map(Coin::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
@@ -1,3 +1,4 @@
+use bech32::{ToBase32, Variant};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
@@ -25,3 +26,9 @@ impl CwTemplateContract {
         .into())
     }
 }
+
+/// Encodes `data` as an address with the same bech32 prefix as `address`
+pub fn bech32_address(address: &Addr, data: &[u8]) -> Option<String> {
+    let (prefix, _) = address.as_str().rsplit_once('1')?;
+    bech32::encode(prefix, data.to_base32(), Variant::Bech32).ok()
+}
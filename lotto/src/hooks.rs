@@ -0,0 +1,27 @@
+use cosmwasm_std::{to_binary, Order, StdResult, Storage, SubMsg, WasmMsg};
+
+use crate::msg::{HookExecuteMsg, LottoHook};
+use crate::reply::HOOK_REPLY_ID;
+use crate::state::HOOKS;
+
+// A hook running out of gas is caught like any other failure
+pub const HOOK_GAS_LIMIT: u64 = 300_000;
+
+/// Notifies every registered hook contract of `hook`
+pub fn hook_msgs(storage: &dyn Storage, hook: LottoHook) -> StdResult<Vec<SubMsg>> {
+    let msg = to_binary(&HookExecuteMsg::LottoHook(hook))?;
+    HOOKS
+        .keys(storage, None, None, Order::Ascending)
+        .map(|contract| {
+            Ok(SubMsg::reply_on_error(
+                WasmMsg::Execute {
+                    contract_addr: contract?.into_string(),
+                    msg: msg.clone(),
+                    funds: vec![],
+                },
+                HOOK_REPLY_ID,
+            )
+            .with_gas_limit(HOOK_GAS_LIMIT))
+        })
+        .collect()
+}
@@ -0,0 +1,164 @@
+use cosmwasm_std::{Addr, BankMsg, Coin, Env, IbcMsg, IbcTimeout, StdResult, Storage, SubMsg};
+use sha2::{Digest, Sha256};
+
+use crate::helpers::bech32_address;
+use crate::ledger::credit_player_balance;
+use crate::reply::IBC_PAYOUT_REPLY_ID;
+use crate::state::{
+    CrossChainPayout, IbcPayout, PayoutChannel, CREDIT_WINNINGS, CROSS_CHAIN_ACCOUNTS,
+    CROSS_CHAIN_PARTICIPANTS, IBC_PAYOUTS_SENT, LOTTO_PAYOUT_CHANNELS, PAYOUT_CHANNELS,
+};
+use crate::ContractError;
+
+// How long an ICS20 transfer paying a cross-chain participant may take to be relayed
+pub const IBC_TRANSFER_TIMEOUT_SECONDS: u64 = 60 * 60;
+
+const IBC_HOOKS_SENDER_PREFIX: &str = "ibc-wasm-hook-intermediary";
+
+/// The account IBC-hooks executes the wasm memo of an ICS20 transfer from.
+/// It is derived from the channel the transfer arrived on and the sender on the counterparty
+/// chain, so it proves who sent the funds. The address has the same bech32 prefix as
+/// `contract`.
+pub fn ibc_hooks_sender(
+    contract: &Addr,
+    channel: &str,
+    original_sender: &str,
+) -> Result<String, ContractError> {
+    let hash = Sha256::new()
+        .chain_update(Sha256::digest(IBC_HOOKS_SENDER_PREFIX))
+        .chain_update(format!("{channel}/{original_sender}"))
+        .finalize();
+    bech32_address(contract, &hash).ok_or(ContractError::InvalidIbcHooksSender)
+}
+
+/// True if `address` has the bech32 prefix of `contract`. Bech32 addresses are case
+/// insensitive, so an upper case address of this chain counts too.
+pub fn is_local_address(contract: &Addr, address: &str) -> bool {
+    match contract.as_str().rsplit_once('1') {
+        Some((prefix, _)) => address.to_lowercase().starts_with(&format!("{prefix}1")),
+        None => false,
+    }
+}
+
+enum PayoutDestination {
+    Bank(Addr),
+    Ibc(PayoutChannel),
+    // Credited to the player balance of the owner
+    Balance,
+}
+
+// Participants who bought their tickets over IBC are paid the way they chose, then the
+// payout channel registered for the lotto wins over crediting the player balance and over
+// the payout channel registered for all lottos. IBC-hooks accounts without any of them are
+// paid back to their original sender.
+fn payout_destination(
+    storage: &dyn Storage,
+    lotto_id: Option<u64>,
+    owner: &Addr,
+) -> StdResult<PayoutDestination> {
+    if let Some(lotto_id) = lotto_id {
+        if let Some(cross_chain) = CROSS_CHAIN_PARTICIPANTS.may_load(storage, (lotto_id, owner))? {
+            return Ok(match cross_chain.payout {
+                CrossChainPayout::Payback => PayoutDestination::Ibc(PayoutChannel {
+                    channel: cross_chain.channel,
+                    remote_address: cross_chain.original_sender,
+                }),
+                CrossChainPayout::Local(address) => PayoutDestination::Bank(address),
+            });
+        }
+        if let Some(channel) = LOTTO_PAYOUT_CHANNELS.may_load(storage, (owner, lotto_id))? {
+            return Ok(PayoutDestination::Ibc(channel));
+        }
+        if CREDIT_WINNINGS.has(storage, owner) {
+            return Ok(PayoutDestination::Balance);
+        }
+    }
+    if let Some(channel) = PAYOUT_CHANNELS.may_load(storage, owner)? {
+        return Ok(PayoutDestination::Ibc(channel));
+    }
+    Ok(match CROSS_CHAIN_ACCOUNTS.may_load(storage, owner)? {
+        Some(account) => PayoutDestination::Ibc(account),
+        None => PayoutDestination::Bank(owner.clone()),
+    })
+}
+
+/// Sends `coin` owed to `owner` for the lotto `lotto_id`, or for no lotto in particular.
+/// ICS20 transfers are tracked so that the funds can be credited back to `owner` if the
+/// transfer fails. No message is needed if the funds are credited to the player balance.
+pub fn payout_msg(
+    storage: &mut dyn Storage,
+    env: &Env,
+    lotto_id: Option<u64>,
+    owner: &Addr,
+    coin: Coin,
+) -> StdResult<Option<SubMsg>> {
+    let msg = match payout_destination(storage, lotto_id, owner)? {
+        PayoutDestination::Balance => {
+            credit_player_balance(storage, owner, &coin)?;
+            return Ok(None);
+        }
+        PayoutDestination::Bank(address) => SubMsg::new(BankMsg::Send {
+            to_address: address.into_string(),
+            amount: vec![coin],
+        }),
+        PayoutDestination::Ibc(PayoutChannel {
+            channel,
+            remote_address,
+        }) => {
+            // Replies arrive in the order the transfers are sent
+            IBC_PAYOUTS_SENT.push_back(
+                storage,
+                &IbcPayout {
+                    owner: owner.clone(),
+                    channel: channel.clone(),
+                    coin: coin.clone(),
+                },
+            )?;
+            // Replying on error too, so that a bad channel doesn't block the settlement
+            SubMsg::reply_always(
+                IbcMsg::Transfer {
+                    channel_id: channel,
+                    to_address: remote_address,
+                    amount: coin,
+                    timeout: IbcTimeout::with_timestamp(
+                        env.block.time.plus_seconds(IBC_TRANSFER_TIMEOUT_SECONDS),
+                    ),
+                },
+                IBC_PAYOUT_REPLY_ID,
+            )
+        }
+    };
+    Ok(Some(msg))
+}
+
+/// The packet sequence in the `MsgTransferResponse` returned for an ICS20 transfer
+pub fn transfer_sequence(data: &[u8]) -> Option<u64> {
+    let mut data = data;
+    while !data.is_empty() {
+        let key = read_varint(&mut data)?;
+        match (key >> 3, key & 7) {
+            (1, 0) => return read_varint(&mut data),
+            (_, 0) => {
+                read_varint(&mut data)?;
+            }
+            (_, 2) => {
+                let len = read_varint(&mut data)? as usize;
+                data = data.get(len..)?;
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+fn read_varint(data: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for (i, byte) in data.iter().enumerate().take(10) {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            *data = &data[i + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
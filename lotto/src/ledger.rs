@@ -0,0 +1,66 @@
+//! Bookkeeping of the funds the contract owes. Every credit to an account owed to someone
+//! adds to the escrow of its denom and every debit releases it, so that the solvency
+//! check and the withdrawals can rely on the escrow.
+
+use cosmwasm_std::{Addr, Coin, StdResult, Storage, Uint128};
+
+use crate::state::{CLAIMABLE, ESCROW, PLAYER_BALANCES};
+use crate::ContractError;
+
+// Player balances are owed to the players, so they stay escrowed
+pub fn credit_player_balance(
+    storage: &mut dyn Storage,
+    player: &Addr,
+    coin: &Coin,
+) -> StdResult<()> {
+    PLAYER_BALANCES.update(storage, (player, &coin.denom), |balance| -> StdResult<_> {
+        Ok(balance.unwrap_or_default() + coin.amount)
+    })?;
+    add_escrow(storage, &coin.denom, coin.amount)
+}
+
+// Takes `coin` out of the balance of `player`. It leaves the escrow with it.
+pub fn debit_player_balance(
+    storage: &mut dyn Storage,
+    player: &Addr,
+    coin: &Coin,
+) -> Result<(), ContractError> {
+    let balance = PLAYER_BALANCES
+        .may_load(storage, (player, &coin.denom))?
+        .unwrap_or_default();
+    if balance < coin.amount {
+        return Err(ContractError::InsufficientBalance {
+            denom: coin.denom.clone(),
+        });
+    }
+    let remaining = balance - coin.amount;
+    if remaining.is_zero() {
+        PLAYER_BALANCES.remove(storage, (player, &coin.denom));
+    } else {
+        PLAYER_BALANCES.save(storage, (player, &coin.denom), &remaining)?;
+    }
+    release_escrow(storage, &coin.denom, coin.amount)?;
+    Ok(())
+}
+
+// Funds owed to `owner` stay escrowed until they are claimed
+pub fn credit_claimable(storage: &mut dyn Storage, owner: &Addr, coin: &Coin) -> StdResult<()> {
+    CLAIMABLE.update(storage, (owner, &coin.denom), |claimable| -> StdResult<_> {
+        Ok(claimable.unwrap_or_default() + coin.amount)
+    })?;
+    add_escrow(storage, &coin.denom, coin.amount)
+}
+
+pub fn add_escrow(storage: &mut dyn Storage, denom: &str, amount: Uint128) -> StdResult<()> {
+    ESCROW.update(storage, denom.to_string(), |escrowed| -> StdResult<_> {
+        Ok(escrowed.unwrap_or_default() + amount)
+    })?;
+    Ok(())
+}
+
+pub fn release_escrow(storage: &mut dyn Storage, denom: &str, amount: Uint128) -> StdResult<()> {
+    ESCROW.update(storage, denom.to_string(), |escrowed| -> StdResult<_> {
+        Ok(escrowed.unwrap_or_default().checked_sub(amount)?)
+    })?;
+    Ok(())
+}
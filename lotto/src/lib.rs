@@ -1,7 +1,14 @@
 pub mod contract;
 mod error;
+pub mod events;
 pub mod helpers;
+pub mod hooks;
+pub mod ibc;
+mod ledger;
 pub mod msg;
+pub mod randomness;
+pub mod reply;
+pub mod signature;
 pub mod state;
 
 pub use crate::error::ContractError;
@@ -1,6 +1,8 @@
-use crate::state::Lotto;
+use crate::state::{
+    CommunityPoolMode, Lotto, LottoStatus, PayoutChannel, RandomnessSource, Stats, VoteWeighting,
+};
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::{Coin, Timestamp, Uint128};
+use cosmwasm_std::{Binary, Coin, Decimal, HexBinary, Timestamp, Uint128};
 use nois::NoisCallback;
 
 #[cw_serde]
@@ -12,6 +14,16 @@ pub struct InstantiateMsg {
     pub protocol_commission_percent: u32,
     // commission that will got to the creator of the lotto
     pub creator_commission_percent: u32,
+    // Defaults to Nois
+    pub randomness_source: Option<RandomnessSource>,
+    // Defaults to 30 days
+    pub max_duration_seconds: Option<u64>,
+    // Defaults to any denom
+    pub allowed_denoms: Option<Vec<String>>,
+    // Defaults to Address
+    pub community_pool_mode: Option<CommunityPoolMode>,
+    // Contract used by `ConvertRevenue`, conversions are disabled if not set
+    pub swap_router: Option<String>,
 }
 
 #[cw_serde]
@@ -22,6 +34,17 @@ pub enum ExecuteMsg {
         duration_seconds: u64,
         number_of_winners: u32,
         community_pool_percentage: u32,
+        // If set, the creator reveals a secret after expiration that is mixed into the randomness,
+        // together with the beacon booked for after the reveal deadline. This is the sha256 hash
+        // of that secret.
+        commitment: Option<HexBinary>,
+        // Registered beneficiary receiving the community pool share, defaults to the community pool
+        beneficiary: Option<String>,
+        // If set, participants vote with their tickets on the beneficiaries sharing the
+        // community pool share. Without votes the share goes to the beneficiary above.
+        vote_weighting: Option<VoteWeighting>,
+        // Label shared by recurring lottos that players can subscribe to
+        series: Option<String>,
     },
     // TODO Kais, Update Config
     SetConfig {
@@ -32,20 +55,198 @@ pub enum ExecuteMsg {
         protocol_commission_percent: Option<u32>,
         creator_commission_percent: Option<u32>,
         is_paused: Option<bool>,
+        randomness_source: Option<RandomnessSource>,
+        max_duration_seconds: Option<u64>,
+        // An empty list accepts any denom
+        allowed_denoms: Option<Vec<String>>,
+        community_pool_mode: Option<CommunityPoolMode>,
+        swap_router: Option<SwapRouterMsg>,
+        // Lets lottos be created without funds, paying their Nois fee out of the reserve
+        reserve_pays_nois_fee: Option<bool>,
     },
+    // Paid out of the player balance of the sender if sent without funds
     BuyTicket {
         lotto_id: u64,
+        // Mixed into the randomness of commit-reveal lottos, rejected by the others
+        entropy: Option<HexBinary>,
+        // Registered beneficiary the ticket votes for, only in lottos with voting
+        vote: Option<String>,
+    },
+    // Buys a ticket out of the player balance of the holder of `buyer_pubkey`.
+    // Anyone can submit it, so that a relayer pays the gas on behalf of the buyer.
+    BuyTicketWithSignature {
+        lotto_id: u64,
+        // Compressed secp256k1 public key of the buyer
+        buyer_pubkey: Binary,
+        // Signature of the sha256 hash of the JSON encoded `TicketAuthorization`
+        signature: Binary,
+        // Has to be the next nonce of the buyer, see `QueryMsg::Nonce`
+        nonce: u64,
+        deadline: Timestamp,
+    },
+    // Credits the sent funds to the player balance of `recipient`, defaulting to the sender
+    DepositBalance {
+        recipient: Option<String>,
+    },
+    // Sends `coins` out of the player balance of the sender to the sender
+    WithdrawBalance {
+        coins: Vec<Coin>,
+    },
+    // Credit winnings and refunds to the player balance of the sender instead of sending them,
+    // unless they go to a payout channel registered for the lotto
+    SetCreditWinnings {
+        enabled: bool,
+    },
+    // Buys `tickets_per_lotto` tickets out of the player balance of the sender in every
+    // lotto created from now on that matches the criteria, until `max_spend` is spent.
+    // Needs a player balance in the denom of `max_spend`, the subscription ends once the
+    // balance can't pay for a ticket. Replaces the subscription the sender already has.
+    Subscribe {
+        criteria: SubscriptionCriteriaMsg,
+        tickets_per_lotto: u32,
+        max_spend: Coin,
+    },
+    Unsubscribe {},
+    // Buys a ticket with the funds of an ICS20 transfer, put in its IBC-hooks wasm memo.
+    // The ticket belongs to the IBC-hooks account of the sender on the counterparty chain,
+    // which no local account can collide with.
+    BuyTicketIbc {
+        lotto_id: u64,
+        // Channel on this chain the transfer arrives through
+        channel: String,
+        // Sender of the transfer on the counterparty chain, never an address of this chain
+        original_sender: String,
+        entropy: Option<HexBinary>,
+        vote: Option<String>,
+        // Where winnings and refunds go, fixed by the first ticket bought in the lotto
+        payout: CrossChainPayoutMsg,
     },
     //callback contains the randomness from drand (HexBinary) and job_id
     //callback should only be allowed to be called by the proxy contract
+    //the randomness is only stored here, the payouts happen in `Settle`
     NoisReceive {
         callback: NoisCallback,
     },
+    // Delivers deterministic randomness to lottos created with the mock randomness source
+    #[cfg(any(test, feature = "mock"))]
+    ReceiveMockRandomness {
+        job_id: String,
+    },
+    // The creator of a commit-reveal lotto reveals its secret after expiration.
+    // The winners are drawn once the beacon arrives.
+    Reveal {
+        lotto_id: u64,
+        secret: HexBinary,
+    },
+    // Anyone can draw the winners of a lotto once its randomness is received and pay them out.
+    // Large lottos are paid in several batches of at most `limit` winners.
+    Settle {
+        lotto_id: u64,
+        limit: Option<u32>,
+    },
+    // The participants of a commit-reveal lotto whose secret was not revealed in time are refunded.
+    // The refund starts when the beacon arrives, or with this message after the reveal deadline.
+    // Anyone can pay it out, in batches of at most `limit` tickets.
+    RefundLotto {
+        lotto_id: u64,
+        limit: Option<u32>,
+    },
     // Withdraw all available balance to the withdrawal address for a specific denom
     WithdrawAll {
         address: String,
         denom: String,
     },
+    // Withdraw part of the protocol balances, in one or several denoms
+    Withdraw {
+        recipient: String,
+        coins: Vec<Coin>,
+    },
+    // Withdraw the protocol balances in every denom
+    WithdrawAllDenoms {
+        recipient: String,
+    },
+    // Replace the recipients the protocol revenue is distributed to
+    SetRevenueRecipients {
+        recipients: Vec<RevenueRecipientMsg>,
+    },
+    // Anyone can split the protocol balances among the revenue recipients by weight.
+    // Rounding dust stays in the protocol balances for the next distribution.
+    DistributeRevenue {},
+    // Swap the whole protocol balance in `denom` through the swap router.
+    // The returned funds, at least `min_out`, are credited to the protocol balance in its denom.
+    ConvertRevenue {
+        denom: String,
+        min_out: Coin,
+    },
+    // Register a contract to be notified of lotto events, see `HookExecuteMsg`
+    AddHook {
+        contract: String,
+    },
+    RemoveHook {
+        contract: String,
+    },
+    // Receive winnings and refunds with an ICS20 transfer to `remote_address` over `channel`,
+    // in the lotto `lotto_id` or in every lotto without a payout channel of its own
+    SetPayoutChannel {
+        lotto_id: Option<u64>,
+        channel: String,
+        remote_address: String,
+    },
+    RemovePayoutChannel {
+        lotto_id: Option<u64>,
+    },
+    // Pays out the payouts of `address` whose transfer failed, to the payout channel registered
+    // for all lottos, back to the original sender of an IBC-hooks account or on this chain.
+    // Anyone can claim, the funds only ever go to `address`.
+    // Defaults to the sender.
+    Claim {
+        address: Option<String>,
+    },
+    // Move protocol balance into the reserve used to pay the Nois fee on behalf of lotto creators.
+    // It is only spent while `reserve_pays_nois_fee` is set, and then by anyone creating lottos
+    // without funds, spam included.
+    FundNoisFeeReserve {
+        amount: Coin,
+    },
+    // Approve a grantee that lotto creators can pick as beneficiary
+    AddBeneficiary {
+        address: String,
+        name: String,
+    },
+    // Lottos that settle after the removal pay its share to the community pool instead,
+    // including the share voted for it
+    RemoveBeneficiary {
+        address: String,
+    },
+}
+
+#[cw_serde]
+pub enum CrossChainPayoutMsg {
+    // Back to the original sender over the channel the ticket was bought through
+    Payback {},
+    // To an address on this chain
+    Local { address: String },
+}
+
+#[cw_serde]
+pub enum RevenueDestinationMsg {
+    Address { address: String },
+    // Keeps the share in the contract to pay the Nois fee on behalf of lotto creators
+    NoisFeeReserve {},
+}
+
+#[cw_serde]
+pub enum SwapRouterMsg {
+    Set { address: String },
+    // Disables `ConvertRevenue` until a router is set again
+    Clear {},
+}
+
+#[cw_serde]
+pub struct RevenueRecipientMsg {
+    pub destination: RevenueDestinationMsg,
+    // Share of the protocol revenue relative to the other recipients
+    pub weight: u32,
 }
 
 #[cw_serde]
@@ -56,32 +257,119 @@ pub enum QueryMsg {
     Config {},
     #[returns(LottoResponse)]
     Lotto { lotto_nonce: u64 },
+    /// Re-runs the draw of a settled lotto from its stored proof
+    #[returns(VerifyDrawResponse)]
+    VerifyDraw { lotto_id: u64 },
+    /// Gets the lottos an address bought tickets in, in descending order (new to old)
+    #[returns(AddressLottosResponse)]
+    TicketsByAddress {
+        address: String,
+        // If set only lotto ids smaller than this value are returned
+        start_after: Option<u64>,
+        /// The max number of entries returned, at most 100. Defaults to 100.
+        limit: Option<u32>,
+    },
+    /// Gets the lottos an address won, in descending order (new to old)
+    #[returns(AddressLottosResponse)]
+    WinningsByAddress {
+        address: String,
+        // If set only lotto ids smaller than this value are returned
+        start_after: Option<u64>,
+        /// The max number of entries returned, at most 100. Defaults to 100.
+        limit: Option<u32>,
+    },
+    /// Gets how the current balance of a lotto would be split if it settled now
+    #[returns(PayoutResponse)]
+    SimulatePayout { lotto_id: u64 },
+    /// Gets the chance of an address to win at least one prize of a lotto with the tickets sold so far
+    #[returns(OddsResponse)]
+    Odds { lotto_id: u64, address: String },
+    /// Gets running totals for the whole protocol
+    #[returns(Stats)]
+    Stats {},
+    /// Gets running totals for the lottos of a creator
+    #[returns(Stats)]
+    CreatorStats { creator: String },
     /// Gets protocol balances in all denoms
     #[returns(ProtocolBalancesResponse)]
     ProtocolBalances {},
+    /// Gets the reserve used to pay the Nois fee on behalf of lotto creators
+    #[returns(NoisFeeReserveResponse)]
+    NoisFeeReserve {},
+    /// Gets the contracts notified of lotto events
+    #[returns(HooksResponse)]
+    Hooks {},
+    /// Gets the payout channel of an address for a lotto, or for all lottos
+    #[returns(PayoutChannelResponse)]
+    PayoutChannel {
+        address: String,
+        lotto_id: Option<u64>,
+    },
+    /// Gets the player balance of an address
+    #[returns(PlayerBalanceResponse)]
+    PlayerBalance { address: String },
+    /// Gets the subscriptions in ascending subscriber address order
+    #[returns(SubscriptionsResponse)]
+    Subscriptions {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// Gets the nonce the next ticket authorization of an address has to be signed with
+    #[returns(NonceResponse)]
+    Nonce { address: String },
+    /// Gets the failed payouts an address can claim
+    #[returns(ClaimableResponse)]
+    Claimable { address: String },
+    /// Gets the recipients the protocol revenue is distributed to
+    #[returns(RevenueRecipientsResponse)]
+    RevenueRecipients {},
+    /// Gets the protocol balance withdrawals, most recent first
+    #[returns(WithdrawalsResponse)]
+    Withdrawals {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Compares the funds the contract owes with its bank balances
+    #[returns(SolvencyResponse)]
+    Solvency {},
+    /// Gets the approved beneficiaries in ascending address order
+    #[returns(BeneficiariesResponse)]
+    Beneficiaries {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// Gets the votes cast in a lotto per beneficiary
+    #[returns(VotesResponse)]
+    Votes { lotto_id: u64 },
     /// Gets lottos in descending order (new to old)
     #[returns(LottosResponse)]
     LottosDesc {
         // If set filters on the creator
         creator: Option<String>,
+        // If set filters on the ticket price denom
+        denom: Option<String>,
+        // If set filters on the status
+        status: Option<LottoStatus>,
         // If set filters on active or non active
         is_active: Option<bool>,
         // If set only nonces smaller than this value are returned
         start_after: Option<u64>,
-        /// The max number of entries returned. If you set this too high, your query runs out of gas.
-        /// When unset, an implementation defined default will be used.
+        /// The max number of entries returned, at most 100. Defaults to 100.
         limit: Option<u64>,
     },
     #[returns(LottosResponse)]
     LottosAsc {
         // If set filters on the creator
         creator: Option<String>,
+        // If set filters on the ticket price denom
+        denom: Option<String>,
+        // If set filters on the status
+        status: Option<LottoStatus>,
         // If set filters on active or non active
         is_active: Option<bool>,
         // If set only nonces greater than this value are returned
         start_after: Option<u64>,
-        /// The max number of entries returned. If you set this too high, your query runs out of gas.
-        /// When unset, an implementation defined default will be used.
+        /// The max number of entries returned, at most 100. Defaults to 100.
         limit: Option<u64>,
     },
 }
@@ -105,22 +393,128 @@ pub struct LottoResponse {
     pub creator: String,
     pub number_of_winners: u32,
     pub community_pool_percentage: u32,
+    pub status: LottoStatus,
+    /// Hash of the creator's secret for commit-reveal lottos
+    pub commitment: Option<HexBinary>,
+    /// Time until which the creator of a commit-reveal lotto can reveal its secret
+    pub reveal_deadline: Option<Timestamp>,
+    /// Receives the community pool share, the configured community pool if not set
+    pub beneficiary: Option<String>,
+    /// Set if participants vote on the beneficiaries sharing the community pool share
+    pub vote_weighting: Option<VoteWeighting>,
+    /// Label shared by recurring lottos
+    pub series: Option<String>,
+    /// Where the randomness of the lotto comes from
+    pub randomness_source: RandomnessSource,
 }
 #[cw_serde]
 pub struct LottosResponse {
-    /// True if expired, False if not expired
     pub lottos: Vec<LottoResponse>,
+    /// Set when the query stopped before the end of the lottos, pass it as `start_after` to
+    /// continue. The query looks at a bounded number of lottos, so a page can come back short or
+    /// even empty while more matching lottos follow.
+    pub next_start_after: Option<u64>,
+}
+
+#[cw_serde]
+pub struct AddressLotto {
+    pub lotto_id: u64,
+    /// Number of tickets the address bought in the lotto
+    pub tickets: u32,
+    /// Total prize the address won in the lotto, zero if it didn't win (yet)
+    pub amount_won: Uint128,
+    /// Ticket price denom of the lotto
+    pub denom: String,
+}
+
+#[cw_serde]
+pub struct AddressLottosResponse {
+    pub lottos: Vec<AddressLotto>,
+}
+
+#[cw_serde]
+pub struct PayoutResponse {
+    pub lotto_id: u64,
+    /// The balance being split
+    pub balance: Coin,
+    pub community_pool: Coin,
+    pub creator: Coin,
+    pub protocol: Coin,
+    /// Prize paid to each winner
+    pub per_winner: Coin,
+    /// How many winners would be drawn from the current participants
+    pub winners: u32,
 }
 
+#[cw_serde]
+pub struct OddsResponse {
+    pub lotto_id: u64,
+    pub address: String,
+    /// Tickets bought by the address
+    pub tickets: u32,
+    /// Tickets sold in total
+    pub total_tickets: u32,
+    /// Probability to be drawn for at least one of the winning slots, rounded down to 18 decimals
+    pub probability: Decimal,
+}
+
+#[cw_serde]
+pub struct VerifyDrawResponse {
+    pub lotto_id: u64,
+    /// The randomness the winners were picked with
+    pub randomness: HexBinary,
+    /// The randomness delivered by the beacon
+    pub beacon: HexBinary,
+    /// When the beacon was published
+    pub published: Timestamp,
+    /// The secret revealed by the creator of a commit-reveal lotto
+    pub secret: Option<HexBinary>,
+    /// The entropy of the participants of a commit-reveal lotto. The randomness is the sha256
+    /// hash of the secret, the entropy and the beacon.
+    pub entropy: Option<HexBinary>,
+    /// The participants the winners were picked from
+    pub participants: Vec<String>,
+    /// The winners stored on the lotto
+    pub winners: Vec<String>,
+    /// True if the randomness derives from the beacon, the participants are the ones the draw
+    /// was made from and picking from them with the randomness gives the stored winners
+    pub is_valid: bool,
+}
+
+/// Version of the `ConfigResponse` layout, bumped whenever a field is added or changes meaning
+pub const CONFIG_RESPONSE_VERSION: u32 = 4;
+
 #[cw_serde]
 pub struct ConfigResponse {
+    /// Layout version of this response, see `CONFIG_RESPONSE_VERSION`
+    pub version: u32,
     /// manager if none set to info.sender.
     pub manager: String,
     /// Address of the Nois proxy contract
     pub nois_proxy: String,
+    /// Id of the next lotto
+    pub lotto_nonce: u64,
+    /// Receives the community pool share of every lotto
+    pub community_pool: String,
+    /// Share of every lotto kept by the protocol
+    pub protocol_commission_percent: u32,
+    /// Share of every lotto paid to its creator
+    pub creator_commission_percent: u32,
     /// If set to true the contract is paused
     /// When a contract is paused the creation of lottos is not possible
     pub is_paused: bool,
+    /// Where the randomness used to draw the winners comes from
+    pub randomness_source: RandomnessSource,
+    /// Longest duration a lotto can be created with
+    pub max_duration_seconds: u64,
+    /// Denoms tickets can be priced in, any denom is accepted if empty
+    pub allowed_denoms: Vec<String>,
+    /// How the community share of a lotto reaches the community pool
+    pub community_pool_mode: CommunityPoolMode,
+    /// Contract used to convert protocol revenue into another denom
+    pub swap_router: Option<String>,
+    /// Whether lottos created without funds get their Nois fee paid out of the reserve
+    pub reserve_pays_nois_fee: bool,
 }
 
 #[cw_serde]
@@ -128,3 +522,241 @@ pub struct ProtocolBalancesResponse {
     /// list of all balances in different denoms
     pub balances: Vec<Coin>,
 }
+
+#[cw_serde]
+pub struct NoisFeeReserveResponse {
+    /// list of all reserve balances in different denoms
+    pub balances: Vec<Coin>,
+}
+
+#[cw_serde]
+pub struct BeneficiaryResponse {
+    pub address: String,
+    pub name: String,
+}
+
+#[cw_serde]
+pub struct BeneficiariesResponse {
+    pub beneficiaries: Vec<BeneficiaryResponse>,
+}
+
+#[cw_serde]
+pub struct VoteTally {
+    pub beneficiary: String,
+    /// Tickets that voted for the beneficiary
+    pub tickets: u32,
+    /// Participants that voted for the beneficiary
+    pub voters: u32,
+    /// Voting power after weighting, the community pool share is split proportionally to it
+    pub weight: Decimal,
+}
+
+#[cw_serde]
+pub struct VotesResponse {
+    pub lotto_id: u64,
+    pub vote_weighting: Option<VoteWeighting>,
+    pub tallies: Vec<VoteTally>,
+}
+
+#[cw_serde]
+pub struct HooksResponse {
+    pub hooks: Vec<String>,
+}
+
+#[cw_serde]
+pub struct PayoutChannelResponse {
+    pub address: String,
+    pub lotto_id: Option<u64>,
+    /// Unset if the address has no payout channel registered for the lotto
+    pub payout_channel: Option<PayoutChannel>,
+}
+
+#[cw_serde]
+pub struct PlayerBalanceResponse {
+    pub address: String,
+    pub coins: Vec<Coin>,
+    /// Whether winnings and refunds are credited to the balance
+    pub credit_winnings: bool,
+}
+
+// A lotto has to match every criterion that is set
+#[cw_serde]
+pub struct SubscriptionCriteriaMsg {
+    pub creator: Option<String>,
+    pub denom: Option<String>,
+    // Highest ticket price, in the denom of the spending cap
+    pub max_price: Option<Uint128>,
+    // Anyone can name a series, so it is only matched for the lottos of `creator`
+    pub series: Option<String>,
+}
+
+#[cw_serde]
+pub struct SubscriptionResponse {
+    pub subscriber: String,
+    pub criteria: SubscriptionCriteriaMsg,
+    pub tickets_per_lotto: u32,
+    pub max_spend: Coin,
+    /// Spent so far, the subscription ends once it reaches `max_spend`
+    pub spent: Uint128,
+}
+
+#[cw_serde]
+pub struct SubscriptionsResponse {
+    pub subscriptions: Vec<SubscriptionResponse>,
+}
+
+#[cw_serde]
+pub struct NonceResponse {
+    pub address: String,
+    pub nonce: u64,
+}
+
+#[cw_serde]
+pub struct ClaimableResponse {
+    pub address: String,
+    pub coins: Vec<Coin>,
+}
+
+#[cw_serde]
+pub struct RevenueRecipientsResponse {
+    pub recipients: Vec<RevenueRecipientMsg>,
+}
+
+#[cw_serde]
+pub struct WithdrawalResponse {
+    pub id: u64,
+    /// The manager for withdrawals, anyone for revenue distributions
+    pub initiator: String,
+    pub recipient: String,
+    pub coins: Vec<Coin>,
+    pub time: Timestamp,
+    pub height: u64,
+}
+
+#[cw_serde]
+pub struct WithdrawalsResponse {
+    pub withdrawals: Vec<WithdrawalResponse>,
+}
+
+#[cw_serde]
+pub struct DenomSolvency {
+    pub denom: String,
+    /// Bank balance of the contract
+    pub balance: Uint128,
+    /// Funds owed to participants, winners, the protocol and the Nois fee reserve
+    pub escrowed: Uint128,
+    /// Balance not owed to anyone, e.g. rounding dust or direct transfers
+    pub surplus: Uint128,
+    /// Funds owed but missing from the balance, should always be zero
+    pub shortfall: Uint128,
+    /// ICS20 payouts sent without a packet sequence, so a failed one isn't credited back to its
+    /// owner. Its funds come back as surplus, which is then owed to the owner.
+    pub untracked_payouts: Uint128,
+}
+
+#[cw_serde]
+pub struct SolvencyResponse {
+    /// True if no denom has a shortfall
+    pub is_solvent: bool,
+    /// Every denom the contract holds or owes, in ascending order
+    pub denoms: Vec<DenomSolvency>,
+}
+
+/// Sent to the registered hook contracts, which have to accept it as execute message.
+/// A failing hook doesn't revert the lotto transaction.
+#[cw_serde]
+pub enum HookExecuteMsg {
+    LottoHook(LottoHook),
+}
+
+#[cw_serde]
+pub enum LottoHook {
+    LottoCreated {
+        lotto_id: u64,
+        creator: String,
+        ticket_price: Coin,
+        expiration: Timestamp,
+    },
+    TicketBought {
+        lotto_id: u64,
+        buyer: String,
+        ticket_price: Coin,
+    },
+    /// Sent once every winner is paid
+    LottoSettled {
+        lotto_id: u64,
+        winners: Vec<String>,
+        prize_per_winner: Coin,
+    },
+}
+
+/// The swap interface a router has to implement to convert protocol revenue.
+/// The offered funds are attached and the output is sent back to the sender.
+#[cw_serde]
+pub enum SwapRouterExecuteMsg {
+    Swap { ask_denom: String, min_out: Uint128 },
+}
+
+/// The subset of the Nois proxy queries this contract relies on
+#[cw_serde]
+pub enum NoisProxyQueryMsg {
+    /// Get the price of a randomness request in the given denom
+    Price { denom: String },
+}
+
+#[cw_serde]
+pub struct NoisPriceResponse {
+    /// None if the denom is not accepted by the proxy
+    pub price: Option<Uint128>,
+}
+
+/// What a buyer signs to let anyone buy a ticket for them out of their player balance.
+/// The contract and chain id keep the signature from being replayed elsewhere.
+#[cw_serde]
+pub struct TicketAuthorization {
+    pub contract: String,
+    pub chain_id: String,
+    pub lotto_id: u64,
+    pub nonce: u64,
+    /// The authorization can't be used after this time
+    pub deadline: Timestamp,
+}
+
+/// Callbacks of Neutron's transfer module for the ICS20 transfers sent by the contract.
+/// The module wraps the ICS20 application and calls back every contract that sent a transfer
+/// packet, so they arrive for the `MsgTransfer` wasmd dispatches for a plain `IbcMsg::Transfer`
+/// as well as for a `NeutronMsg::IbcTransfer`. On a chain without them a failed payout isn't
+/// credited back, its funds show up as surplus in the `Solvency` query.
+#[cw_serde]
+pub enum SudoMsg {
+    /// The transfer was acknowledged
+    Response {
+        request: RequestPacket,
+        data: Binary,
+    },
+    /// The counterparty chain rejected the transfer and the funds were refunded
+    Error {
+        request: RequestPacket,
+        details: String,
+    },
+    /// The transfer timed out and the funds were refunded
+    Timeout { request: RequestPacket },
+}
+
+#[cw_serde]
+pub struct RequestPacket {
+    pub sequence: Option<u64>,
+    pub source_port: Option<String>,
+    pub source_channel: Option<String>,
+    pub destination_port: Option<String>,
+    pub destination_channel: Option<String>,
+    pub data: Option<Binary>,
+    pub timeout_height: Option<RequestPacketTimeoutHeight>,
+    pub timeout_timestamp: Option<u64>,
+}
+
+#[cw_serde]
+pub struct RequestPacketTimeoutHeight {
+    pub revision_number: Option<u64>,
+    pub revision_height: Option<u64>,
+}
@@ -0,0 +1,184 @@
+use cosmwasm_std::{
+    ensure_eq, to_binary, Addr, Coin, CosmosMsg, Deps, Env, MessageInfo, Timestamp, Uint128,
+    WasmMsg,
+};
+use nois::{NoisCallback, ProxyExecuteMsg};
+#[cfg(any(test, feature = "mock"))]
+use sha2::{Digest, Sha256};
+
+use crate::msg::{NoisPriceResponse, NoisProxyQueryMsg};
+use crate::state::{Config, RandomnessSource};
+use crate::ContractError;
+
+/// Randomness delivered for a job, independent of the beacon that produced it.
+/// This is all the game logic needs to know to draw the winners.
+pub struct RandomnessReady {
+    pub job_id: String,
+    pub randomness: [u8; 32],
+    /// When the randomness was published by the beacon
+    pub published: Timestamp,
+}
+
+/// The callbacks a provider can deliver randomness through
+pub enum ProviderCallback {
+    Nois(NoisCallback),
+    #[cfg(any(test, feature = "mock"))]
+    Mock {
+        job_id: String,
+    },
+}
+
+impl ProviderCallback {
+    /// The job the callback claims to deliver randomness for, not authenticated yet
+    pub fn job_id(&self) -> &str {
+        match self {
+            ProviderCallback::Nois(callback) => &callback.job_id,
+            #[cfg(any(test, feature = "mock"))]
+            ProviderCallback::Mock { job_id } => job_id,
+        }
+    }
+}
+
+/// A source of randomness that can be booked for a point in time and then delivers it
+/// back to the contract through a callback
+pub trait RandomnessProvider {
+    /// The price of one randomness request in the given denom.
+    /// Returns None if the denom is not accepted.
+    fn price(&self, deps: Deps, denom: &str) -> Result<Option<Uint128>, ContractError>;
+
+    /// True if requests can be booked without paying any fee
+    fn is_free(&self) -> bool {
+        false
+    }
+
+    /// Books randomness published after `after`, paid with `funds`
+    fn request(
+        &self,
+        job_id: String,
+        after: Timestamp,
+        funds: Vec<Coin>,
+    ) -> Result<Vec<CosmosMsg>, ContractError>;
+
+    /// Authenticates a callback and extracts the randomness from it
+    fn receive(
+        &self,
+        env: &Env,
+        info: &MessageInfo,
+        callback: ProviderCallback,
+    ) -> Result<RandomnessReady, ContractError>;
+}
+
+/// Returns the provider of a randomness source
+pub fn provider(config: &Config, source: &RandomnessSource) -> Box<dyn RandomnessProvider> {
+    match source {
+        RandomnessSource::Nois => Box::new(NoisProvider {
+            proxy: config.nois_proxy.clone(),
+        }),
+        #[cfg(any(test, feature = "mock"))]
+        RandomnessSource::Mock => Box::new(MockProvider),
+    }
+}
+
+/// Randomness from drand, delivered by the Nois proxy
+pub struct NoisProvider {
+    pub proxy: Addr,
+}
+
+impl RandomnessProvider for NoisProvider {
+    fn price(&self, deps: Deps, denom: &str) -> Result<Option<Uint128>, ContractError> {
+        let NoisPriceResponse { price } = deps.querier.query_wasm_smart(
+            &self.proxy,
+            &NoisProxyQueryMsg::Price {
+                denom: denom.to_string(),
+            },
+        )?;
+        Ok(price)
+    }
+
+    fn request(
+        &self,
+        job_id: String,
+        after: Timestamp,
+        funds: Vec<Coin>,
+    ) -> Result<Vec<CosmosMsg>, ContractError> {
+        Ok(vec![WasmMsg::Execute {
+            contract_addr: self.proxy.to_string(),
+            // GetRandomnessAfter requests the randomness from the proxy after a specific timestamp
+            // The job id is needed to know what randomness we are referring to upon reception in the callback.
+            msg: to_binary(&ProxyExecuteMsg::GetRandomnessAfter { after, job_id })?,
+            funds,
+        }
+        .into()])
+    }
+
+    fn receive(
+        &self,
+        _env: &Env,
+        info: &MessageInfo,
+        callback: ProviderCallback,
+    ) -> Result<RandomnessReady, ContractError> {
+        // Only the Nois callback exists without the `mock` feature
+        #[allow(clippy::infallible_destructuring_match)]
+        let callback = match callback {
+            ProviderCallback::Nois(callback) => callback,
+            #[cfg(any(test, feature = "mock"))]
+            _ => return Err(ContractError::UnauthorizedReceive),
+        };
+        // callback should only be allowed to be called by the proxy contract
+        // otherwise anyone can cut the randomness workflow and cheat the randomness by sending the randomness directly to this contract
+        ensure_eq!(info.sender, self.proxy, ContractError::UnauthorizedReceive);
+        let randomness: [u8; 32] = callback
+            .randomness
+            .to_array()
+            .map_err(|_| ContractError::InvalidRandomness)?;
+        Ok(RandomnessReady {
+            job_id: callback.job_id,
+            randomness,
+            published: callback.published,
+        })
+    }
+}
+
+/// Deterministic randomness for local and multi-test setups. Never use this on a live chain,
+/// anyone can trigger the callback and predict its outcome.
+#[cfg(any(test, feature = "mock"))]
+pub struct MockProvider;
+
+#[cfg(any(test, feature = "mock"))]
+impl RandomnessProvider for MockProvider {
+    fn price(&self, _deps: Deps, _denom: &str) -> Result<Option<Uint128>, ContractError> {
+        Ok(Some(Uint128::zero()))
+    }
+
+    fn is_free(&self) -> bool {
+        true
+    }
+
+    fn request(
+        &self,
+        _job_id: String,
+        _after: Timestamp,
+        _funds: Vec<Coin>,
+    ) -> Result<Vec<CosmosMsg>, ContractError> {
+        // Nothing to book, the randomness is delivered through `ReceiveMockRandomness`
+        Ok(vec![])
+    }
+
+    fn receive(
+        &self,
+        env: &Env,
+        _info: &MessageInfo,
+        callback: ProviderCallback,
+    ) -> Result<RandomnessReady, ContractError> {
+        let job_id = match callback {
+            ProviderCallback::Mock { job_id } => job_id,
+            _ => return Err(ContractError::UnauthorizedReceive),
+        };
+        let randomness = Sha256::digest(job_id.as_bytes()).into();
+        Ok(RandomnessReady {
+            job_id,
+            randomness,
+            published: env.block.time,
+        })
+    }
+}
@@ -0,0 +1,9 @@
+//! Ids of the submessages the contract handles replies for. They live together so that
+//! a new one can't reuse an id already taken in another module.
+
+// Reply of the swap router to a `ConvertRevenue`
+pub const CONVERT_REVENUE_REPLY_ID: u64 = 1;
+// Replies only arrive for failed hooks, so that they never revert the transaction
+pub const HOOK_REPLY_ID: u64 = 2;
+// Reply carrying the packet sequence of an ICS20 payout, or its failure
+pub const IBC_PAYOUT_REPLY_ID: u64 = 3;
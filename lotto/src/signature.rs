@@ -0,0 +1,40 @@
+use cosmwasm_std::{to_binary, Addr, Binary, Deps, StdResult};
+use ripemd::Ripemd160;
+use sha2::{Digest, Sha256};
+
+use crate::helpers::bech32_address;
+use crate::msg::TicketAuthorization;
+use crate::ContractError;
+
+/// The address of the account holding `pubkey`, derived like the Cosmos SDK does for
+/// secp256k1 keys. It has the same bech32 prefix as `contract`.
+pub fn pubkey_address(contract: &Addr, pubkey: &[u8]) -> Result<Addr, ContractError> {
+    let hash = Ripemd160::digest(Sha256::digest(pubkey));
+    bech32_address(contract, &hash)
+        .map(Addr::unchecked)
+        .ok_or(ContractError::InvalidSignature)
+}
+
+/// The hash a buyer signs to authorize a ticket purchase
+pub fn authorization_hash(authorization: &TicketAuthorization) -> StdResult<[u8; 32]> {
+    Ok(Sha256::digest(to_binary(authorization)?).into())
+}
+
+/// Fails unless `signature` is a signature of `authorization` by `pubkey`
+pub fn verify_authorization(
+    deps: Deps,
+    authorization: &TicketAuthorization,
+    pubkey: &Binary,
+    signature: &Binary,
+) -> Result<(), ContractError> {
+    let hash = authorization_hash(authorization)?;
+    // Malformed signatures and keys are as invalid as wrong ones
+    let valid = deps
+        .api
+        .secp256k1_verify(&hash, signature, pubkey)
+        .unwrap_or(false);
+    if !valid {
+        return Err(ContractError::InvalidSignature);
+    }
+    Ok(())
+}
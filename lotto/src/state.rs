@@ -1,6 +1,6 @@
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{Addr, Coin, Timestamp, Uint128};
-use cw_storage_plus::{Item, Map};
+use cosmwasm_std::{Addr, Coin, Decimal, Empty, HexBinary, Timestamp, Uint128};
+use cw_storage_plus::{Deque, Index, IndexList, IndexedMap, Item, Map, MultiIndex};
 
 #[cw_serde]
 pub struct Config {
@@ -16,6 +16,36 @@ pub struct Config {
     // If set to true the contract is paused
     // When a contract is paused the creation of lottos is not possible
     pub is_paused: bool,
+    // Where the randomness used to draw the winners comes from
+    pub randomness_source: RandomnessSource,
+    // Longest duration a lotto can be created with
+    pub max_duration_seconds: u64,
+    // Denoms tickets can be priced in, any denom is accepted if empty
+    pub allowed_denoms: Vec<String>,
+    // How the community share of a lotto reaches the community pool
+    pub community_pool_mode: CommunityPoolMode,
+    // Contract used to convert protocol revenue into another denom
+    pub swap_router: Option<Addr>,
+    // If set the Nois fee of lottos created without funds is paid out of the Nois fee reserve.
+    // Anyone can create lottos, so anyone can spend the reserve while it is set.
+    pub reserve_pays_nois_fee: bool,
+}
+
+#[cw_serde]
+pub enum CommunityPoolMode {
+    // Bank send to the `community_pool` address, e.g. Neutron's community pool contract
+    Address,
+    // MsgFundCommunityPool to the x/distribution module, requires the `distribution` feature
+    Distribution,
+}
+
+#[cw_serde]
+pub enum RandomnessSource {
+    // drand randomness delivered by the Nois proxy
+    Nois,
+    // Deterministic randomness for local chains and multi-test, requires the `mock` feature
+    #[cfg(any(test, feature = "mock"))]
+    Mock,
 }
 
 #[cw_serde]
@@ -40,12 +70,383 @@ pub struct Lotto {
     pub number_of_winners: u32,
     // Community pool percentage
     pub community_pool_percentage: u32,
+    pub status: LottoStatus,
+    // Set if the randomness comes from a secret committed by the creator instead of a beacon
+    pub commit_reveal: Option<CommitReveal>,
+    // Everything needed to re-run the draw, set once the randomness is received
+    pub draw: Option<DrawProof>,
+    // Progress of the payouts, set by the first settlement batch
+    pub settlement: Option<Settlement>,
+    // Tickets paid back so far, refunds are paid in batches
+    pub tickets_refunded: u32,
+    // Receives the community pool share instead of the configured community pool
+    pub beneficiary: Option<Addr>,
+    // Set if participants vote on the beneficiaries sharing the community pool share
+    pub vote_weighting: Option<VoteWeighting>,
+    // Where the randomness comes from, fixed when the lotto is created
+    pub randomness_source: RandomnessSource,
+    // Groups recurring lottos, e.g. "daily-ntrn", so that players can subscribe to them
+    pub series: Option<String>,
+}
+
+#[cw_serde]
+pub enum VoteWeighting {
+    // Every ticket is one vote
+    Linear,
+    // A participant voting with n tickets for a beneficiary weighs sqrt(n)
+    Quadratic,
+}
+
+#[cw_serde]
+pub enum LottoStatus {
+    // Accepting tickets until expiration, then waiting for the randomness
+    Open,
+    // Randomness received, the winners are being paid out in batches
+    Settling,
+    // Winners have been drawn and paid
+    Settled,
+    // Participants got their tickets refunded
+    Refunded,
+    // Secret not revealed in time, the participants are being refunded in batches
+    Refunding,
+}
+
+// What an address did in a lotto, kept per address so that its history can be listed
+#[cw_serde]
+pub struct TicketsRecord {
+    pub tickets: u32,
+    // Ticket price denom of the lotto
+    pub denom: String,
+}
+
+#[cw_serde]
+pub struct WinningsRecord {
+    pub amount_won: Uint128,
+    pub denom: String,
+}
+
+// Running totals, kept for the whole protocol and per creator.
+// Amounts are per denom. The tickets of refunded lottos are taken out again.
+#[cw_serde]
+#[derive(Default)]
+pub struct Stats {
+    pub lottos_created: u64,
+    pub tickets_sold: u64,
+    // Value of all tickets sold
+    pub volume: Vec<Coin>,
+    pub prizes_paid: Vec<Coin>,
+    pub creator_commissions: Vec<Coin>,
+    pub community_pool_contributions: Vec<Coin>,
+    pub protocol_revenue: Vec<Coin>,
+}
+
+impl LottoStatus {
+    // Key of the status in the lottos status index
+    pub fn index_key(&self) -> u8 {
+        match self {
+            LottoStatus::Open => 0,
+            LottoStatus::Settling => 1,
+            LottoStatus::Settled => 2,
+            LottoStatus::Refunded => 3,
+            LottoStatus::Refunding => 4,
+        }
+    }
+}
+
+#[cw_serde]
+pub struct DrawProof {
+    // The randomness the winners were picked with
+    pub randomness: HexBinary,
+    // The randomness delivered by the beacon. Commit-reveal lottos mix it with the secret and
+    // the entropy stored on the lotto, the others use it as is.
+    pub beacon: HexBinary,
+    // When the beacon was published. For Nois this identifies the drand round.
+    pub published: Timestamp,
+    // sha256 hash of the JSON encoded participants of the lotto, in ticket order
+    pub participants_hash: HexBinary,
+}
+
+#[cw_serde]
+pub struct Settlement {
+    // The prize paid to each winner
+    pub amount_winner: Uint128,
+    // How many winners have been paid so far
+    pub winners_paid: u32,
+    // Commissions paid by the first batch
+    pub amount_creator: Uint128,
+    pub amount_protocol: Uint128,
+    pub amount_community_pool: Uint128,
+}
+
+#[cw_serde]
+pub struct CommitReveal {
+    // sha256 hash of the secret the creator reveals after expiration
+    pub commitment: HexBinary,
+    // Hash chain of the entropy supplied by participants when buying tickets
+    pub entropy: HexBinary,
+    // The secret has to be revealed by then, else the lotto is refunded.
+    // The beacon mixed into the randomness is published after it.
+    pub reveal_deadline: Timestamp,
+    // Set once the creator revealed it
+    pub secret: Option<HexBinary>,
+}
+
+#[cw_serde]
+pub struct CrossChainParticipant {
+    // Channel on this chain the tickets were bought through
+    pub channel: String,
+    // Sender of the tickets on the counterparty chain
+    pub original_sender: String,
+    pub payout: CrossChainPayout,
+}
+
+#[cw_serde]
+pub enum CrossChainPayout {
+    // ICS20 transfer back to the participant over the channel the tickets were bought through
+    Payback,
+    // Bank send to an address on this chain
+    Local(Addr),
+}
+
+// A payout whose transfer fails is only credited back as claimable if the transfer module
+// returned the packet sequence. Without it the funds of a failed transfer come back as surplus
+// owed to no one, see `untracked_payouts` in the `Solvency` query.
+#[cw_serde]
+pub struct PayoutChannel {
+    // Channel on this chain the ICS20 transfer goes through
+    pub channel: String,
+    // Receiver on the counterparty chain
+    pub remote_address: String,
+}
+
+#[cw_serde]
+pub struct IbcPayout {
+    // Credited with the funds if the transfer fails
+    pub owner: Addr,
+    pub channel: String,
+    pub coin: Coin,
+}
+
+// Buys tickets out of the player balance of the subscriber in every new lotto matching
+// the criteria
+#[cw_serde]
+pub struct Subscription {
+    pub criteria: SubscriptionCriteria,
+    pub tickets_per_lotto: u32,
+    // Only lottos priced in its denom match. The subscription ends once it is spent.
+    pub max_spend: Coin,
+    pub spent: Uint128,
+}
+
+// A lotto has to match every criterion that is set
+#[cw_serde]
+pub struct SubscriptionCriteria {
+    pub creator: Option<Addr>,
+    pub denom: Option<String>,
+    // Highest ticket price, in the denom of the spending cap
+    pub max_price: Option<Uint128>,
+    pub series: Option<String>,
+}
+
+impl Subscription {
+    pub fn matches(&self, lotto: &Lotto) -> bool {
+        let criteria = &self.criteria;
+        lotto.ticket_price.denom == self.max_spend.denom
+            && criteria
+                .creator
+                .iter()
+                .all(|creator| *creator == lotto.creator)
+            && criteria
+                .denom
+                .iter()
+                .all(|denom| denom == &lotto.ticket_price.denom)
+            && criteria
+                .max_price
+                .iter()
+                .all(|max_price| lotto.ticket_price.amount <= *max_price)
+            && criteria
+                .series
+                .iter()
+                .all(|series| Some(series) == lotto.series.as_ref())
+    }
+}
+
+#[cw_serde]
+pub struct Beneficiary {
+    // Display name of the grantee
+    pub name: String,
+}
+
+// Votes for a beneficiary in a lotto, updated with every ticket so that settling never has to
+// go through the votes of each participant
+#[cw_serde]
+#[derive(Default)]
+pub struct Tally {
+    pub tickets: u32,
+    pub voters: u32,
+    // Voting power after the weighting of the lotto
+    pub weight: Decimal,
+}
+
+#[cw_serde]
+pub struct Withdrawal {
+    // Who triggered the withdrawal: the manager for `Withdraw` and `WithdrawAll`, anyone for
+    // `DistributeRevenue`. Where the funds went is decided by the manager either way.
+    pub initiator: Addr,
+    pub recipient: Addr,
+    pub coins: Vec<Coin>,
+    pub time: Timestamp,
+    pub height: u64,
+}
+
+#[cw_serde]
+pub struct RevenueRecipient {
+    pub destination: RevenueDestination,
+    // Share of the protocol revenue relative to the other recipients
+    pub weight: u32,
+}
+
+#[cw_serde]
+pub enum RevenueDestination {
+    Address(Addr),
+    // Stays in the contract to pay the Nois fee on behalf of lotto creators
+    NoisFeeReserve,
+}
+
+#[cw_serde]
+pub struct PendingConversion {
+    // Protocol revenue sent to the swap router
+    pub offer: Coin,
+    // Minimum amount the swap has to return
+    pub min_out: Coin,
+    // Contract balance in the returned denom before the swap
+    pub balance_before: Uint128,
 }
 
 pub const CONFIG_KEY: &str = "config";
 pub const LOTTOS_KEY: &str = "lottos";
+pub const LOTTOS_CREATOR_KEY: &str = "lottos__creator";
+pub const LOTTOS_DENOM_KEY: &str = "lottos__denom";
+pub const LOTTOS_STATUS_KEY: &str = "lottos__status";
 pub const PROTOCOL_BALANCES_KEY: &str = "balances";
+pub const NOIS_FEE_RESERVE_KEY: &str = "nois_fee_reserve";
+pub const TICKETS_KEY: &str = "tickets";
+pub const STATS_KEY: &str = "stats";
+pub const CREATOR_STATS_KEY: &str = "creator_stats";
+pub const WINNINGS_KEY: &str = "winnings";
+pub const BENEFICIARIES_KEY: &str = "beneficiaries";
+pub const VOTES_KEY: &str = "votes";
+pub const VOTE_TALLIES_KEY: &str = "vote_tallies";
+pub const ESCROW_KEY: &str = "escrow";
+pub const WITHDRAWALS_KEY: &str = "withdrawals";
+pub const REVENUE_RECIPIENTS_KEY: &str = "revenue_recipients";
+pub const PENDING_CONVERSION_KEY: &str = "pending_conversion";
+pub const HOOKS_KEY: &str = "hooks";
+pub const CROSS_CHAIN_PARTICIPANTS_KEY: &str = "cross_chain_participants";
+pub const CROSS_CHAIN_ACCOUNTS_KEY: &str = "cross_chain_accounts";
+pub const PAYOUT_CHANNELS_KEY: &str = "payout_channels";
+pub const LOTTO_PAYOUT_CHANNELS_KEY: &str = "lotto_payout_channels";
+pub const IBC_PAYOUTS_SENT_KEY: &str = "ibc_payouts_sent";
+pub const IBC_PAYOUTS_KEY: &str = "ibc_payouts";
+pub const UNTRACKED_PAYOUTS_KEY: &str = "untracked_payouts";
+pub const CLAIMABLE_KEY: &str = "claimable";
+pub const PLAYER_BALANCES_KEY: &str = "player_balances";
+pub const NONCES_KEY: &str = "nonces";
+pub const CREDIT_WINNINGS_KEY: &str = "credit_winnings";
+pub const SUBSCRIPTIONS_KEY: &str = "subscriptions";
+pub const SUBSCRIPTION_CURSOR_KEY: &str = "subscription_cursor";
+pub const WITHDRAWAL_COUNT_KEY: &str = "withdrawal_count";
 
 pub const CONFIG: Item<Config> = Item::new(CONFIG_KEY);
-pub const LOTTOS: Map<u64, Lotto> = Map::new(LOTTOS_KEY);
+pub const LOTTOS: IndexedMap<u64, Lotto, LottoIndexes> = IndexedMap::new(
+    LOTTOS_KEY,
+    LottoIndexes {
+        creator: MultiIndex::new(
+            |_, lotto| lotto.creator.clone(),
+            LOTTOS_KEY,
+            LOTTOS_CREATOR_KEY,
+        ),
+        denom: MultiIndex::new(
+            |_, lotto| lotto.ticket_price.denom.clone(),
+            LOTTOS_KEY,
+            LOTTOS_DENOM_KEY,
+        ),
+        status: MultiIndex::new(
+            |_, lotto| lotto.status.index_key(),
+            LOTTOS_KEY,
+            LOTTOS_STATUS_KEY,
+        ),
+    },
+);
 pub const PROTOCOL_BALANCES: Map<String, Uint128> = Map::new(PROTOCOL_BALANCES_KEY);
+// Funds set aside by the manager out of the protocol balances to pay the Nois proxy
+// on behalf of lotto creators, so that creating a lotto can be free
+pub const NOIS_FEE_RESERVE: Map<String, Uint128> = Map::new(NOIS_FEE_RESERVE_KEY);
+pub const STATS: Item<Stats> = Item::new(STATS_KEY);
+pub const CREATOR_STATS: Map<&Addr, Stats> = Map::new(CREATOR_STATS_KEY);
+// Tickets bought and prizes won, keyed by address then lotto id
+pub const TICKETS: Map<(&Addr, u64), TicketsRecord> = Map::new(TICKETS_KEY);
+pub const WINNINGS: Map<(&Addr, u64), WinningsRecord> = Map::new(WINNINGS_KEY);
+
+// Secondary indexes so that lottos can be listed without scanning all of them
+pub struct LottoIndexes<'a> {
+    pub creator: MultiIndex<'a, Addr, Lotto, u64>,
+    // Ticket price denom
+    pub denom: MultiIndex<'a, String, Lotto, u64>,
+    pub status: MultiIndex<'a, u8, Lotto, u64>,
+}
+
+impl<'a> IndexList<Lotto> for LottoIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<Lotto>> + '_> {
+        let v: Vec<&dyn Index<Lotto>> = vec![&self.creator, &self.denom, &self.status];
+        Box::new(v.into_iter())
+    }
+}
+// Grantees approved by the manager that lotto creators can send their community share to
+pub const BENEFICIARIES: Map<&Addr, Beneficiary> = Map::new(BENEFICIARIES_KEY);
+// Tickets voting for a beneficiary, keyed by lotto id, beneficiary then voter
+pub const VOTES: Map<(u64, &Addr, &Addr), u32> = Map::new(VOTES_KEY);
+// Sum of the votes of a lotto per beneficiary, keyed by lotto id then beneficiary
+pub const VOTE_TALLIES: Map<(u64, &Addr), Tally> = Map::new(VOTE_TALLIES_KEY);
+// What the contract owes per denom: ticket funds of open lottos, prizes not paid yet,
+// protocol balances, the Nois fee reserve, claimable payouts and player balances.
+// The bank balance must always cover it.
+pub const ESCROW: Map<String, Uint128> = Map::new(ESCROW_KEY);
+// Audit log of the protocol balance withdrawals, keyed by a sequential id
+pub const WITHDRAWALS: Map<u64, Withdrawal> = Map::new(WITHDRAWALS_KEY);
+pub const WITHDRAWAL_COUNT: Item<u64> = Item::new(WITHDRAWAL_COUNT_KEY);
+// Where `DistributeRevenue` sends the protocol balances
+pub const REVENUE_RECIPIENTS: Item<Vec<RevenueRecipient>> = Item::new(REVENUE_RECIPIENTS_KEY);
+// Set while a revenue conversion waits for the reply of the swap router
+pub const PENDING_CONVERSION: Item<PendingConversion> = Item::new(PENDING_CONVERSION_KEY);
+// Contracts notified when lottos are created, tickets bought and lottos settled
+pub const HOOKS: Map<&Addr, Empty> = Map::new(HOOKS_KEY);
+// Participants who bought tickets over IBC, keyed by lotto id then their IBC-hooks account.
+// An entry is never overwritten, so the payout can't be redirected by later tickets.
+pub const CROSS_CHAIN_PARTICIPANTS: Map<(u64, &Addr), CrossChainParticipant> =
+    Map::new(CROSS_CHAIN_PARTICIPANTS_KEY);
+// The channel and the counterparty sender behind an IBC-hooks account that bought tickets.
+// What the account can claim goes back there, since nobody holds the keys of the account.
+pub const CROSS_CHAIN_ACCOUNTS: Map<&Addr, PayoutChannel> = Map::new(CROSS_CHAIN_ACCOUNTS_KEY);
+// Where ICS20 transfers pay an address, for all lottos or keyed by address then lotto id
+pub const PAYOUT_CHANNELS: Map<&Addr, PayoutChannel> = Map::new(PAYOUT_CHANNELS_KEY);
+pub const LOTTO_PAYOUT_CHANNELS: Map<(&Addr, u64), PayoutChannel> =
+    Map::new(LOTTO_PAYOUT_CHANNELS_KEY);
+// ICS20 payouts sent in the current transaction, waiting for the reply with their sequence
+pub const IBC_PAYOUTS_SENT: Deque<IbcPayout> = Deque::new(IBC_PAYOUTS_SENT_KEY);
+// ICS20 payouts waiting for their acknowledgement, keyed by channel then packet sequence
+pub const IBC_PAYOUTS: Map<(&str, u64), IbcPayout> = Map::new(IBC_PAYOUTS_KEY);
+// Total of the ICS20 payouts sent without a packet sequence to wait for, per denom
+pub const UNTRACKED_PAYOUTS: Map<String, Uint128> = Map::new(UNTRACKED_PAYOUTS_KEY);
+// Payouts whose transfer failed, keyed by owner then denom
+pub const CLAIMABLE: Map<(&Addr, &str), Uint128> = Map::new(CLAIMABLE_KEY);
+// Funds players deposited or won, to buy tickets with. Keyed by address then denom.
+pub const PLAYER_BALANCES: Map<(&Addr, &str), Uint128> = Map::new(PLAYER_BALANCES_KEY);
+// Next nonce of the ticket authorizations signed by an address
+pub const NONCES: Map<&Addr, u64> = Map::new(NONCES_KEY);
+// Players whose winnings and refunds are credited to their player balance
+pub const CREDIT_WINNINGS: Map<&Addr, Empty> = Map::new(CREDIT_WINNINGS_KEY);
+// One subscription per subscriber
+pub const SUBSCRIPTIONS: Map<&Addr, Subscription> = Map::new(SUBSCRIPTIONS_KEY);
+// Last subscriber checked when a lotto was created, the next lotto resumes after it
+pub const SUBSCRIPTION_CURSOR: Item<Addr> = Item::new(SUBSCRIPTION_CURSOR_KEY);